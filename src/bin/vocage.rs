@@ -1,6 +1,7 @@
 extern crate rand;
 extern crate clap;
 extern crate termion;
+extern crate regex;
 extern crate chrono;
 extern crate vocage;
 
@@ -10,18 +11,134 @@ use termion::screen::AlternateScreen;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode};
 use termion::color;
-use std::io::{Write, stdout, stdin};
-use clap::{Arg, App};
-use rand::prelude::{thread_rng,Rng};
-use vocage::{VocaSession,VocaCard,PrintFormat,load_files};
+use std::io::{Write, BufRead, stdout, stdin, stderr};
+use clap::{Arg, App, AppSettings, SubCommand};
+use rand::prelude::{Rng,SeedableRng,SliceRandom};
+use rand::rngs::StdRng;
+use std::time::Instant;
+use std::collections::HashSet;
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::Regex;
+use unicode_width::{UnicodeWidthStr,UnicodeWidthChar};
+use vocage::{VocaSession,VocaData,VocaCard,CardState,DailyCounts,Schedule,CardFilter,FilterTerm,PickOptions,VocaStats,Forecast,PrintFormat,Grade,Scheduler,ReviewAction,ReviewLog,ReviewLogEntry,load_files,pick_distractors,current_time,local_date};
 
 static NUMCHARS: &[char] = &['1','2','3','4','5','6','7','8','9'];
+static GRADES: &[(char, Grade)] = &[('1',Grade::Again),('2',Grade::Hard),('3',Grade::Good),('4',Grade::Easy)];
+
+///Source of the current time, which can be shifted (--now) to simulate studying at another time
+#[derive(Debug, Copy, Clone)]
+struct Clock {
+    offset: chrono::Duration,
+}
+
+///Tally of a review session, printed as a summary at the end of the session
+struct SessionSummary {
+    started: Instant,
+    ///Number of cards on each deck at the start of the session
+    decks: Vec<usize>,
+    reviewed: usize,
+    promoted: usize,
+    demoted: usize,
+    retained: usize,
+    skipped: usize,
+}
+
+///State of a card before an action was taken on it, to be able to undo the action
+struct UndoEntry {
+    setindex: usize,
+    cardindex: usize,
+    schedule: Schedule,
+    state: CardState,
+    lapses: u32,
+    ///Daily counts of the dataset before the action
+    studied: DailyCounts,
+    ///Whether there were unsaved changes before the action
+    changed: bool,
+    ///The action, the deck the card ended up on (to correct the session summary) and the time it was logged at
+    action: Option<(ReviewAction, u8, NaiveDateTime)>,
+}
+
+///Filter on the due state of cards in the browser
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum DueFilter {
+    All,
+    Due,
+    NotDue,
+    Unseen,
+}
 
 fn main() {
     let args = App::new("Vocage :: Flash cards")
                   .version("1.0")
                   .author("Maarten van Gompel (proycon) <proycon@anaproy.nl>")
                   .about("A simple command-line flash card system implementing spaced-repetition (Leitner)")
+                  .setting(AppSettings::SubcommandsNegateReqs)
+                  .subcommand(SubCommand::with_name("stats")
+                    .about("Show statistics on the learning progress")
+                    .args(&file_arguments())
+                    .arg(clock_argument())
+                    .arg(Arg::with_name("minimal")
+                        .takes_value(true)
+                        .short("-m")
+                        .long("minimal")
+                        .help("Output format, either 'plain' or 'colour' (default)")
+                    )
+                    .arg(Arg::with_name("json")
+                        .long("json")
+                        .short("-j")
+                        .help("Output statistics as JSON")
+                    )
+                    .arg(Arg::with_name("log")
+                        .long("log")
+                        .takes_value(true)
+                        .help("Review log to compute retention from. By default, the log files alongside the vocabulary files are used.")
+                    )
+                    .args(&VocaSession::common_arguments())
+                  )
+                  .subcommand(SubCommand::with_name("forecast")
+                    .about("Show how many reviews are due on each of the upcoming days")
+                    .args(&file_arguments())
+                    .arg(clock_argument())
+                    .arg(Arg::with_name("days")
+                        .long("days")
+                        .short("-n")
+                        .takes_value(true)
+                        .help("Number of days to forecast (default: 14)")
+                    )
+                    .args(&VocaSession::common_arguments())
+                  )
+                  .subcommand(SubCommand::with_name("list")
+                    .about("List cards, one per line: file, deck, due date, state, variant and the fields, separated by tabs (e.g. use --filter flagged to list all flagged cards)")
+                    .args(&file_arguments())
+                    .arg(clock_argument())
+                    .arg(Arg::with_name("filter")
+                        .long("filter")
+                        .short("-F")
+                        .takes_value(true)
+                        .help("Only list cards matching this query (see the main --filter option), the terms suspended, buried and flagged select cards by their state")
+                    )
+                    .args(&VocaSession::common_arguments())
+                  )
+                  .subcommand(SubCommand::with_name("leeches")
+                    .about("List the cards marked as a leech (see --leechthreshold), most lapses first")
+                    .args(&file_arguments())
+                    .args(&VocaSession::common_arguments())
+                  )
+                  .subcommand(SubCommand::with_name("add")
+                    .about("Add a new card to a vocabulary file")
+                    .arg(Arg::with_name("file")
+                        .help("vocabulary file (tsv)")
+                        .takes_value(true)
+                        .index(1)
+                        .required(true)
+                    )
+                    .arg(Arg::with_name("fields")
+                        .help("The contents of each of the columns, in order. If not specified, you will be prompted for each column, or, if standard input is not a terminal, cards are read from standard input (one per line, columns separated by tabs)")
+                        .takes_value(true)
+                        .multiple(true)
+                        .index(2)
+                    )
+                  )
                   .arg(Arg::with_name("force")
                     .long("force")
                     .short("-f")
@@ -38,6 +155,27 @@ fn main() {
                     .takes_value(true)
                     .help("Limit to this deck only (all decks will be considered by default)")
                    )
+                  .arg(Arg::with_name("filter")
+                    .long("filter")
+                    .short("-F")
+                    .takes_value(true)
+                    .help("Only present cards matching this query, a space separated list of terms that must all match: deck:N (also deck:>=N, deck:<N, deck:NAME), due:<7d (time until due, units m, h, d, w), COLUMN=VALUE (field equals value), COLUMN~VALUE (field contains value), due, seen, unseen, suspended, buried, flagged, leech. Prefix a term with - to negate it, use double quotes for values with spaces")
+                   )
+                  .arg(Arg::with_name("tag")
+                    .long("tag")
+                    .short("-T")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Only present cards with this tag (in the column named 'tags'), can be specified multiple times to present cards with any of the tags")
+                   )
+                  .arg(Arg::with_name("exclude-tag")
+                    .long("exclude-tag")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Do not present cards with this tag, can be specified multiple times")
+                   )
                   .arg(Arg::with_name("firstdeck")
                     .long("firstdeck")
                     .short("-A")
@@ -74,6 +212,51 @@ fn main() {
                     .short("-z")
                     .help("Show cards in the order they are defined rather than randomly. Can also be toggled at runtime with 'z'")
                    )
+                  .arg(Arg::with_name("graded")
+                    .long("graded")
+                    .short("-g")
+                    .help("Grade answers with the number keys 1 (again), 2 (hard), 3 (good) and 4 (easy) rather than moving cards to a deck. Can also be toggled at runtime with 'g'")
+                   )
+                  .arg(Arg::with_name("typed")
+                    .long("typed")
+                    .short("-t")
+                    .help("Type the answer rather than just flipping the card, vocage then checks it against the next side of the card and suggests a grade. Can also be toggled at runtime with 't'")
+                   )
+                  .arg(Arg::with_name("quiz")
+                    .long("quiz")
+                    .short("-Q")
+                    .help("Multiple choice mode: choose the answer from a number of options with the number keys, the other options are taken from other cards. Can also be toggled at runtime with 'c'")
+                   )
+                  .arg(Arg::with_name("choices")
+                    .long("choices")
+                    .takes_value(true)
+                    .help("Number of options to choose from in multiple choice mode (default: 4)")
+                   )
+                  .arg(clock_argument())
+                  .arg(Arg::with_name("seed")
+                    .long("seed")
+                    .takes_value(true)
+                    .help("Seed for the random number generator (an integer), so the cards are presented in the same order again given the same input. Combine with --now to replay a session exactly")
+                   )
+                  .arg(Arg::with_name("max-cards")
+                    .long("max-cards")
+                    .takes_value(true)
+                    .help("End the session after reviewing this many cards, progress is saved automatically and a summary is shown")
+                   )
+                  .arg(Arg::with_name("max-time")
+                    .long("max-time")
+                    .takes_value(true)
+                    .help("End the session after this many minutes (the current card is finished first), progress is saved automatically and a summary is shown")
+                   )
+                  .arg(Arg::with_name("log")
+                    .long("log")
+                    .takes_value(true)
+                    .help("Append all review actions to this log file. By default, every vocabulary file gets its own log file alongside it (with extension .log)")
+                   )
+                  .arg(Arg::with_name("nolog")
+                    .long("nolog")
+                    .help("Do not keep a review log")
+                   )
                   .arg(Arg::with_name("reset")
                     .long("reset")
                     .help("Reset the loaded deck, this strips the due date and deck assignment of all cards")
@@ -81,18 +264,44 @@ fn main() {
                   .get_matches();
 
 
-    let mut rng = thread_rng();
-
-    let mut datasets = load_files(args.values_of("files").unwrap().collect(), args.is_present("force"), args.is_present("reset"));
-    for dataset in datasets.iter_mut() {
-        dataset.session.set_common_arguments(&args).expect("setting common arguments");
-        if dataset.session.decks.is_empty() && dataset.session.intervals.is_empty() {
-            //no decks or intervals defined yet, set some defaults
-            dataset.session.decks = vec!("immediate","daily","weekly","monthly","quarterly","yearly").iter().map(|s| s.to_string()).collect();
-            dataset.session.intervals = vec!(0,1440,10080,43200,129600,518400);
+    if let Some(args) = args.subcommand_matches("stats") {
+        stats(args);
+        return;
+    } else if let Some(args) = args.subcommand_matches("list") {
+        list(args);
+        return;
+    } else if let Some(args) = args.subcommand_matches("leeches") {
+        leeches(args);
+        return;
+    } else if let Some(args) = args.subcommand_matches("add") {
+        add(args);
+        return;
+    } else if let Some(args) = args.subcommand_matches("forecast") {
+        let datasets = load(args);
+        let days = args.value_of("days").map(|s| s.parse::<usize>().expect("expecting an integer")).unwrap_or(14);
+        let width = termion::terminal_size().map(|(width, _)| width as usize).unwrap_or(80);
+        for line in forecast_lines(&Forecast::new(&datasets, &Clock::new(args).now(), days), width) {
+            println!("{}", line);
         }
+        return;
     }
 
+    let mut rng: StdRng = match args.value_of("seed") {
+        Some(seed) => StdRng::seed_from_u64(seed.parse::<u64>().expect("expecting an integer")),
+        None => StdRng::from_entropy(),
+    };
+    let clock = Clock::new(&args);
+
+    let mut datasets = load(&args);
+
+    let logs: Vec<ReviewLog> = if args.is_present("nolog") {
+        Vec::new()
+    } else if let Some(filename) = args.value_of("log") {
+        datasets.iter().map(|_| ReviewLog::new(filename)).collect()
+    } else {
+        args.values_of("files").unwrap().map(ReviewLog::sidecar).collect()
+    };
+
     let limit_decks: Option<Vec<u8>> = if args.is_present("limit") {
         Some(vec!(datasets[0].session.get_deck_by_name(args.value_of("limit").unwrap()).unwrap()))
     } else if args.is_present("firstdeck") || args.is_present("lastdeck") {
@@ -102,10 +311,28 @@ fn main() {
     } else {
         None
     };
+    let mut filter: Option<CardFilter> = args.value_of("filter").map(|query| {
+        CardFilter::parse(query, &datasets[0].session).unwrap_or_else(|err| {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        })
+    });
+    if let Some(tags) = args.values_of("tag") {
+        filter.get_or_insert_with(CardFilter::default).terms.push((false, FilterTerm::Tags(tags.map(|tag| tag.to_owned()).collect())));
+    }
+    if let Some(tags) = args.values_of("exclude-tag") {
+        filter.get_or_insert_with(CardFilter::default).terms.push((true, FilterTerm::Tags(tags.map(|tag| tag.to_owned()).collect())));
+    }
     let mut due_only: bool = !args.is_present("all");
     let mut seen_only: bool = args.is_present("seen");
     let mut ordered: bool = args.is_present("ordered");
+    let mut graded: bool = args.is_present("graded");
+    let mut typed: bool = args.is_present("typed");
+    let mut quiz: bool = args.is_present("quiz");
+    let numchoices: usize = args.value_of("choices").map(|s| s.parse::<usize>().expect("expecting an integer")).unwrap_or(4).clamp(2, 9);
     let mut reset: bool = args.is_present("reset");
+    let max_cards: Option<usize> = args.value_of("max-cards").map(|s| s.parse::<usize>().expect("expecting an integer"));
+    let max_time: Option<u64> = args.value_of("max-time").map(|s| s.parse::<u64>().expect("expecting an integer"));
     let minimal: Option<PrintFormat> = match args.value_of("minimal") {
         None => None,
         Some("color") | Some("colour") => Some(PrintFormat::AnsiColour),
@@ -124,12 +351,16 @@ fn main() {
 
     let mut history: Vec<(usize,usize)> = Vec::new();
     let mut pick_specific: Option<(usize,usize)> = None; //(set,card), will select a random card if set to None
+    let mut siblings: Vec<HashSet<usize>> = datasets.iter().map(|_| HashSet::new()).collect(); //siblings of presented cards (other clozes/directions of the same line), not to be presented in this session
 
     let mut duecards = 0;
     let mut tries = 0;
     let mut changed = false;
     let mut confirmexitstage = false;
     let mut pressed_q = false;
+    let mut lasttag = String::new();
+    let mut summary = SessionSummary::new(&datasets, &clock.now());
+    let mut undostack: Vec<UndoEntry> = Vec::new();
 
     //make a copy to prevent problems with the borrow checker
     let session = datasets[0].session.clone();
@@ -138,8 +369,17 @@ fn main() {
         if changed {
             reset = false;
         }
-        if let Some(card) = match pick_specific {
-                Some((setindex, cardindex)) => datasets[setindex].cards.get_mut(cardindex), //pick a specific card
+        if max_cards.is_some_and(|max_cards| summary.reviewed >= max_cards) || max_time.is_some_and(|max_time| summary.started.elapsed().as_secs() >= max_time * 60) {
+            //session goal reached
+            for dataset in datasets.iter() {
+                dataset.write(reset).expect("failure saving file");
+            }
+            break;
+        }
+        let now = clock.now();
+        if let Some((setindex, cardindex)) = match pick_specific {
+                Some((setindex, cardindex)) if cardindex < datasets[setindex].cards.len() => Some((setindex, cardindex)), //pick a specific card
+                Some(_) => None,
                 None => {
                     if ordered {
                         //pick a card in order
@@ -147,8 +387,9 @@ fn main() {
                         let mut nextindex = None;
                         let mut cardindex = *cardindex;
                         let mut setindex = *setindex;
-                        for i in setindex..datasets.len() {
-                            nextindex = datasets[i].next_index(cardindex, limit_decks.as_ref(), due_only, seen_only, history.is_empty());
+                        for (i, dataset) in datasets.iter().enumerate().skip(setindex) {
+                            let options = PickOptions { decks: limit_decks.as_ref(), due_only, seen_only, exclude: Some(&siblings[i]), filter: filter.as_ref() };
+                            nextindex = dataset.next_index(cardindex, &now, history.is_empty(), &options);
                             if nextindex.is_some() {
                                 setindex = i;
                                 break;
@@ -158,7 +399,7 @@ fn main() {
                         if let Some((cardindex,totalcards)) = nextindex {
                             duecards = totalcards;
                             history.push((setindex,cardindex));
-                            datasets[setindex].cards.get_mut(cardindex)
+                            Some((setindex, cardindex))
                         } else {
                             tries = 999; //no indeterministic factor
                             None
@@ -167,11 +408,12 @@ fn main() {
                         //pick a random set
                         let setindex = if datasets.len() == 1 { 0 } else { rng.gen_range(0,datasets.len()) };
                         //pick a random card
-                        if let Some((cardindex,totalcards)) = datasets[setindex].random_index(&mut rng, limit_decks.as_ref(), due_only, seen_only) {
+                        let options = PickOptions { decks: limit_decks.as_ref(), due_only, seen_only, exclude: Some(&siblings[setindex]), filter: filter.as_ref() };
+                        if let Some((cardindex,totalcards)) = datasets[setindex].random_index(&mut rng, &now, &options) {
                             duecards = totalcards;
                             history.push((setindex,cardindex));
                             tries = 0; //reset
-                            datasets[setindex].cards.get_mut(cardindex)
+                            Some((setindex, cardindex))
                         } else {
                             tries += 1;
                             None
@@ -180,22 +422,96 @@ fn main() {
                 }
             } { //end match block. In ordered mode, cards will be presented in the order they are defined.
             pick_specific = None; //reset
+            siblings[setindex].extend(datasets[setindex].siblings(cardindex));
+            //each file has its own configuration (scheduler, layout), use that of the file the card is in
+            let session = datasets[setindex].session.clone();
+            let scheduler = session.scheduler();
+            //multiple choice: the answer and distractors, in random order
+            let answercolumn: Option<u8> = datasets[setindex].cards[cardindex].answer_column(1, &session);
+            let mut choices: Vec<String> = Vec::new();
+            if let (true, Some(answercolumn)) = (quiz, answercolumn) {
+                choices = pick_distractors(&datasets, setindex, cardindex, answercolumn, numchoices - 1, &mut rng);
+                if let Some(answer) = datasets[setindex].cards[cardindex].answer(answercolumn) {
+                    choices.push(answer);
+                }
+                choices.shuffle(&mut rng);
+            }
+            //state before any action is taken, to be able to undo it
+            let mut previous = UndoEntry {
+                setindex,
+                cardindex,
+                schedule: datasets[setindex].cards[cardindex].schedule(),
+                state: datasets[setindex].cards[cardindex].state,
+                lapses: datasets[setindex].cards[cardindex].lapses,
+                studied: datasets[setindex].session.studied,
+                changed,
+                action: None,
+            };
+            let card = &mut datasets[setindex].cards[cardindex];
             //show card
             let mut side: u8 = 0;
-            draw(&mut stdout, Some(card), &session, side, status.as_str(), history.len(), duecards, minimal);
+            draw(&mut stdout, Some(card), &session, side, status_line(&status, graded, card, scheduler.as_ref(), &clock.now(), &session).as_str(), history.len(), duecards, &choices, minimal);
             status.clear();
+            let presented = Instant::now();
+            let olddeck = card.deck;
+            let wasleech = card.state.leech;
+            let wasnew = card.due.is_none();
+            let mut action: Option<ReviewAction> = None;
+            let mut save = false;
+            let mut show_forecast = false;
+            let mut edit = false;
+            let mut external_edit = false;
+            let mut new_card = false;
+            let mut show_browser = false;
+            let mut tag_prompt = false;
+            let mut undo = false;
+            let mut suggested: Option<Grade> = None;
+
+            if typed && card.sides(&session) > 1 {
+                //read the typed answer
+                let mut answer = String::new();
+                let mut submitted = false;
+                draw(&mut stdout, Some(card), &session, side, "Type your answer (enter to check, esc to skip typing): ", history.len(), duecards, &choices, minimal);
+                for c in stdin().keys() {
+                    match c.unwrap() {
+                        Key::Char('\n') => {
+                            submitted = true;
+                            break;
+                        },
+                        Key::Esc => break,
+                        Key::Backspace => {
+                            answer.pop();
+                        },
+                        Key::Char(c) => answer.push(c),
+                        _ => {}
+                    }
+                    draw(&mut stdout, Some(card), &session, side, format!("Type your answer (enter to check, esc to skip typing): {}", answer).as_str(), history.len(), duecards, &choices, minimal);
+                }
+                if submitted {
+                    side = 1;
+                    if let Some(check) = card.check_answer(&answer, side, &session) {
+                        status = match check.grade {
+                            Grade::Good => "Correct!".to_owned(),
+                            Grade::Hard => format!("Almost, expected: {}", check.expected),
+                            _ => format!("Wrong, expected: {}", check.expected),
+                        };
+                        status = format!("{} - press enter to grade '{}', or grade manually", status, check.grade);
+                        suggested = Some(check.grade);
+                    }
+                    draw(&mut stdout, Some(card), &session, side, status.as_str(), history.len(), duecards, &choices, minimal);
+                    status.clear();
+                }
+            }
 
             //process input
             for c in stdin().keys() {
                 match c.unwrap() {
                      Key::Char('w') => {
-                         for dataset in datasets.iter() {
-                             dataset.write(reset).expect("failure saving file");
-                         }
+                         save = true;
                          status = "Saved...".to_owned();
                          pick_specific = history.pop(); //make sure we re-show the current item
-                         if pick_specific.is_some() {
-                             history.push(pick_specific.clone().unwrap());
+                         if let Some(pick_specific) = pick_specific {
+                             history.push(pick_specific);
                          }
                          changed = false;
                          if confirmexitstage {
@@ -220,51 +536,98 @@ fn main() {
                              break;
                          }
                      },
+                     Key::Char('\n') if suggested.is_some() => {
+                         let grade = suggested.expect("suggested grade");
+                         card.review(grade, scheduler.as_ref(), &clock.now(), &session);
+                         action = Some(ReviewAction::Graded(grade));
+                         status = format!("Card graded '{}', on deck {}: {}, next review in {}", grade, card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned()), format_interval(card.interval(&session))).to_owned();
+                         changed = true;
+                         break;
+                     },
                      Key::Char(' ') | Key::Char('\n') => {
                          side += 1;
-                         if side >= session.showcolumns.len() as u8 {
+                         if side >= card.sides(&session) {
                              side = 0;
                          }
                          //redraw
-                         draw(&mut stdout, Some(card), &session, side, status.as_str(), history.len(), duecards, minimal);
+                         draw(&mut stdout, Some(card), &session, side, status_line(&status, graded, card, scheduler.as_ref(), &clock.now(), &session).as_str(), history.len(), duecards, &choices, minimal);
                      },
                      Key::Char('h') | Key::Left => {
-                         if card.demote(&session) {
+                         let previous = card.review(Grade::Again, scheduler.as_ref(), &clock.now(), &session);
+                         action = Some(ReviewAction::Graded(Grade::Again));
+                         if card.deck < previous.deck {
                              status = format!("Card demoted to deck {}: {}", card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned())  ).to_owned();
-                             changed = true;
                          } else {
                              status = "Already on first deck".to_owned();
                          }
+                         changed = true;
                          break;
                      },
                      Key::Char('l') | Key::Right => {
-                         if card.promote(&session) {
+                         let previous = card.review(Grade::Good, scheduler.as_ref(), &clock.now(), &session);
+                         action = Some(ReviewAction::Graded(Grade::Good));
+                         if card.deck > previous.deck {
                              status = format!("Card promoted to deck {}: {}", card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned())  ).to_owned();
-                             changed = true;
                          } else {
                              status = "Already on last deck".to_owned();
                          }
+                         changed = true;
                          break;
                      },
                      Key::Char('j') | Key::Down => {
-                         card.move_to_deck(card.deck, &session);
+                         card.review(Grade::Hard, scheduler.as_ref(), &clock.now(), &session);
+                         action = Some(ReviewAction::Graded(Grade::Hard));
                          status = format!("Card retained on deck {}: {}", card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned())  ).to_owned();
                          changed = true;
                          break;
                      },
                      Key::Char('J') | Key::PageDown => {
                          status = "Card skipped".to_owned();
+                         action = Some(ReviewAction::Skipped);
                          break;
                      },
+                     Key::Char('u') if !undostack.is_empty() => {
+                         undo = true;
+                         break;
+                     },
+                     Key::Char('u') => {
+                         status = "Nothing to undo".to_owned();
+                     },
                      Key::Char('k') | Key::Up | Key::PageUp => {
+                         //navigation only, this is not logged nor counted as skipping the card
                          status = "Showing previous card".to_owned();
                          pick_specific = history.pop();
                          break;
                      },
+                     Key::Char(c) if !choices.is_empty() && NUMCHARS[..choices.len()].contains(&c) => {
+                         let choice = &choices[(c as u8 - b'1') as usize];
+                         let answer = answercolumn.and_then(|column| card.answer(column)).unwrap_or_default();
+                         if *choice == answer {
+                             card.review(Grade::Good, scheduler.as_ref(), &clock.now(), &session);
+                             action = Some(ReviewAction::Graded(Grade::Good));
+                             status = format!("Correct! Card on deck {}: {}", card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned()));
+                         } else {
+                             card.review(Grade::Again, scheduler.as_ref(), &clock.now(), &session);
+                             action = Some(ReviewAction::Graded(Grade::Again));
+                             status = format!("Wrong, the answer was: {}. Card on deck {}: {}", answer, card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned()));
+                         }
+                         changed = true;
+                         break;
+                     },
+                     Key::Char(c) if graded && GRADES.iter().any(|(key, _)| *key == c) => {
+                         let (_, grade) = GRADES.iter().find(|(key, _)| *key == c).expect("grade");
+                         card.review(*grade, scheduler.as_ref(), &clock.now(), &session);
+                         action = Some(ReviewAction::Graded(*grade));
+                         status = format!("Card graded '{}', on deck {}: {}, next review in {}", grade, card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned()), format_interval(card.interval(&session))).to_owned();
+                         changed = true;
+                         break;
+                     },
                      Key::Char(c) if NUMCHARS.contains(&c) => {
                          let targetdeck = c as u8 - 49;
-                         if card.move_to_deck(targetdeck, &session) {
+                         if card.move_to_deck(targetdeck, &clock.now(), &session) {
+                             action = Some(ReviewAction::Moved);
                              status = format!("Card moved to deck {}: {}", card.deck+1, session.decks.get(card.deck as usize).unwrap_or(&"unspecified".to_owned())  ).to_owned();
+                             changed = true;
                          } else {
                              status = "Invalid deck".to_owned();
                          }
@@ -286,6 +649,98 @@ fn main() {
                              status = "Showing all cards, including those not due".to_owned();
                          }
                      },
+                     Key::Char('x') if card.state.suspended => {
+                         //e.g. a card selected in the browser
+                         card.state.suspended = false;
+                         status = "Card no longer suspended".to_owned();
+                         changed = true;
+                         //redraw
+                         draw(&mut stdout, Some(card), &session, side, status.as_str(), history.len(), duecards, &choices, minimal);
+                     },
+                     Key::Char('x') => {
+                         card.state.suspended = true;
+                         status = "Card suspended, it will no longer be presented".to_owned();
+                         changed = true;
+                         break;
+                     },
+                     Key::Char('B') if card.state.is_buried(&clock.now()) => {
+                         card.state.buried = None;
+                         status = "Card no longer buried".to_owned();
+                         changed = true;
+                         //redraw
+                         draw(&mut stdout, Some(card), &session, side, status.as_str(), history.len(), duecards, &choices, minimal);
+                     },
+                     Key::Char('B') => {
+                         card.state.buried = Some(local_date(&clock.now()) + chrono::Duration::days(1));
+                         status = "Card buried until tomorrow".to_owned();
+                         changed = true;
+                         break;
+                     },
+                     Key::Char('F') => {
+                         card.state.flagged = !card.state.flagged;
+                         if card.state.flagged {
+                             status = "Card flagged".to_owned();
+                         } else {
+                             status = "Card no longer flagged".to_owned();
+                         }
+                         changed = true;
+                         //redraw
+                         draw(&mut stdout, Some(card), &session, side, status.as_str(), history.len(), duecards, &choices, minimal);
+                     },
+                     Key::Char('e') if minimal.is_none() => {
+                         edit = true;
+                         pick_specific = Some((setindex, cardindex)); //re-show the current card afterwards
+                         break;
+                     },
+                     Key::Char('/') | Key::Char('b') if minimal.is_none() => {
+                         show_browser = true;
+                         pick_specific = Some((setindex, cardindex)); //re-show the current card afterwards, unless another one is selected
+                         break;
+                     },
+                     Key::Char('n') if minimal.is_none() => {
+                         new_card = true;
+                         pick_specific = Some((setindex, cardindex)); //re-show the current card afterwards
+                         break;
+                     },
+                     Key::Char('T') => {
+                         tag_prompt = true;
+                         pick_specific = Some((setindex, cardindex)); //re-show the current card afterwards
+                         break;
+                     },
+                     Key::Char('E') => {
+                         external_edit = true;
+                         pick_specific = Some((setindex, cardindex)); //re-show the current card afterwards
+                         break;
+                     },
+                     Key::Char('f') => {
+                         show_forecast = true;
+                         pick_specific = Some((setindex, cardindex)); //re-show the current card afterwards
+                         break;
+                     },
+                     Key::Char('g') => {
+                         graded = !graded;
+                         if graded {
+                             status = "Grading mode: 1 = again, 2 = hard, 3 = good, 4 = easy".to_owned();
+                         } else {
+                             status = "Number keys move cards to the n'th deck".to_owned();
+                         }
+                     },
+                     Key::Char('c') => {
+                         quiz = !quiz;
+                         if quiz {
+                             status = "Multiple choice mode (takes effect from the next card)".to_owned();
+                         } else {
+                             status = "Multiple choice mode disabled".to_owned();
+                         }
+                     },
+                     Key::Char('t') => {
+                         typed = !typed;
+                         if typed {
+                             status = "Typing answers (takes effect from the next card)".to_owned();
+                         } else {
+                             status = "No longer typing answers".to_owned();
+                         }
+                     },
                      Key::Char('z') => {
                          ordered = !ordered;
                          if ordered {
@@ -302,11 +757,200 @@ fn main() {
                      confirmexitstage = false; //reset
                 }
             }
+            if card.state.leech && !wasleech {
+                status = if card.state.suspended {
+                    format!("{} - Card lapsed {} times, marked as leech and suspended", status, card.lapses)
+                } else {
+                    format!("{} - Card lapsed {} times, marked as leech", status, card.lapses)
+                };
+            }
+            if let Some(action) = action {
+                summary.add(action, olddeck, card.deck);
+            }
+            let timestamp = clock.now();
+            if let (Some(action), Some(log)) = (action, logs.get(setindex)) {
+                let entry = ReviewLogEntry {
+                    timestamp,
+                    card: card.identity(&session),
+                    olddeck,
+                    newdeck: card.deck,
+                    action,
+                    duration: presented.elapsed().as_millis() as u64,
+                    side,
+                };
+                if let Err(err) = log.append(&entry) {
+                    status = format!("Unable to write review log {}: {}", log.filename, err);
+                }
+            }
+            if let Some(ReviewAction::Graded(_)) | Some(ReviewAction::Moved) = action {
+                //count towards the daily limits
+                datasets[setindex].session.count_studied(wasnew, &clock.now());
+            }
+            let card = &datasets[setindex].cards[cardindex];
+            if card.schedule() != previous.schedule || card.state != previous.state {
+                previous.action = action.map(|action| (action, card.deck, timestamp));
+                undostack.push(previous);
+            }
+            if undo {
+                let entry = undostack.pop().expect("undo entry");
+                let deck = entry.schedule.deck;
+                let card = &mut datasets[entry.setindex].cards[entry.cardindex];
+                card.set_schedule(entry.schedule);
+                card.state = entry.state;
+                card.lapses = entry.lapses;
+                datasets[entry.setindex].session.studied = entry.studied;
+                if let Some((action, newdeck, _)) = entry.action {
+                    summary.remove(action, deck, newdeck);
+                }
+                changed = entry.changed;
+                status = format!("Undone, card restored to deck {}: {}", deck+1, datasets[entry.setindex].session.decks.get(deck as usize).unwrap_or(&"unspecified".to_owned()));
+                if let (Some((_, newdeck, undone)), Some(log)) = (entry.action, logs.get(entry.setindex)) {
+                    //the undone action stays in the log, mark it as undone (referring to it by the time it was logged)
+                    let logentry = ReviewLogEntry {
+                        timestamp: clock.now(),
+                        card: datasets[entry.setindex].cards[entry.cardindex].identity(&datasets[entry.setindex].session),
+                        olddeck: newdeck,
+                        newdeck: deck,
+                        action: ReviewAction::Undone(Some(undone)),
+                        duration: 0,
+                        side: 0,
+                    };
+                    if let Err(err) = log.append(&logentry) {
+                        status = format!("Unable to write review log {}: {}", log.filename, err);
+                    }
+                }
+                pick_specific = Some((entry.setindex, entry.cardindex)); //re-present the card
+            }
+            if edit {
+                let fields = datasets[setindex].cards[cardindex].fields.clone();
+                if let Some(fields) = edit_fields(&mut stdout, &fields, &session.columns) {
+                    if fields != datasets[setindex].cards[cardindex].fields {
+                        let cardindex = update_line(&mut datasets[setindex], setindex, cardindex, |dataset| dataset.update_fields(cardindex, fields), &mut history, &mut siblings[setindex], &mut undostack);
+                        pick_specific = Some((setindex, cardindex));
+                        status = "Card edited (press w to save)".to_owned();
+                        changed = true;
+                    }
+                } else {
+                    status = "Editing cancelled".to_owned();
+                }
+            }
+            if external_edit {
+                //the whole line, so the state of the other variants can be edited (and is retained) as well
+                let line = datasets[setindex].line_to_string(cardindex, false);
+                match edit_line(&line) {
+                    Ok(Some(newline)) if newline != line => match VocaCard::parse_line(&newline, Some(session.columns.len()), false, 1) {
+                        Ok(newline) => {
+                            let cardindex = update_line(&mut datasets[setindex], setindex, cardindex, |dataset| dataset.replace_line(cardindex, newline), &mut history, &mut siblings[setindex], &mut undostack);
+                            //the edited state takes precedence over any state saved to undo an earlier action
+                            let line = datasets[setindex].line(cardindex);
+                            undostack.retain(|entry| entry.setindex != setindex || !line.contains(&entry.cardindex));
+                            pick_specific = Some((setindex, cardindex));
+                            status = "Card edited (press w to save)".to_owned();
+                            changed = true;
+                        },
+                        Err(err) => status = format!("Unable to parse edited card, discarding changes: {}", err),
+                    },
+                    Ok(_) => status = "Card left unchanged".to_owned(),
+                    Err(err) => status = format!("Unable to edit card: {}", err),
+                }
+            }
+            if tag_prompt {
+                if session.tagcolumn().is_none() {
+                    status = "Unable to tag, there is no column named 'tags'".to_owned();
+                } else {
+                    let mut tag = String::new();
+                    let mut confirmed = false;
+                    let mut keys = stdin().keys();
+                    loop {
+                        let prompt = if lasttag.is_empty() {
+                            format!("Tag to toggle (esc to cancel): {}", tag)
+                        } else {
+                            format!("Tag to toggle (enter for '{}', esc to cancel): {}", lasttag, tag)
+                        };
+                        draw(&mut stdout, Some(&datasets[setindex].cards[cardindex]), &session, side, prompt.as_str(), history.len(), duecards, &choices, minimal);
+                        match keys.next() {
+                            Some(Ok(Key::Char('\n'))) => {
+                                confirmed = true;
+                                break;
+                            },
+                            Some(Ok(Key::Esc)) | None => break,
+                            Some(Ok(Key::Backspace)) => {
+                                tag.pop();
+                            },
+                            Some(Ok(Key::Char(c))) if !c.is_whitespace() && !c.is_control() => tag.push(c),
+                            _ => {},
+                        }
+                    }
+                    if tag.is_empty() {
+                        tag = lasttag.clone();
+                    }
+                    if confirmed && !tag.is_empty() {
+                        let added = datasets[setindex].cards[cardindex].toggle_tag(&tag, &session);
+                        let fields = datasets[setindex].cards[cardindex].fields.clone();
+                        let cardindex = update_line(&mut datasets[setindex], setindex, cardindex, |dataset| dataset.update_fields(cardindex, fields), &mut history, &mut siblings[setindex], &mut undostack);
+                        pick_specific = Some((setindex, cardindex));
+                        status = if added == Some(true) { format!("Tagged card '{}'", tag) } else { format!("Removed tag '{}'", tag) };
+                        lasttag = tag;
+                        changed = true;
+                    }
+                }
+            }
+            if show_browser {
+                if let Some(selected) = browse(&mut stdout, &datasets, &clock.now(), &session) {
+                    pick_specific = Some(selected);
+                }
+            }
+            if new_card {
+                //choose the file that receives the card
+                let target: Option<usize> = if datasets.len() == 1 {
+                    Some(0)
+                } else {
+                    let lines: Vec<String> = datasets.iter().take(NUMCHARS.len()).enumerate().map(|(i, dataset)| format!("{}) {}", i+1, dataset.session.filename().unwrap_or("unnamed"))).collect();
+                    draw_lines(&mut stdout, &lines, "Add the new card to which file? (press its number, esc to cancel)", minimal);
+                    match stdin().keys().next() {
+                        Some(Ok(Key::Char(c))) if NUMCHARS[..lines.len()].contains(&c) => Some((c as u8 - b'1') as usize),
+                        _ => None
+                    }
+                };
+                match target.and_then(|target| edit_fields(&mut stdout, &[], &datasets[target].session.columns).map(|fields| (target, fields))) {
+                    Some((target, fields)) if fields.iter().any(|field| !field.is_empty()) => {
+                        datasets[target].add_card(fields);
+                        status = format!("Card added to {} (press w to save)", datasets[target].session.filename().unwrap_or("unnamed"));
+                        changed = true;
+                    },
+                    _ => status = "No card added".to_owned(),
+                }
+            }
+            if save {
+                for dataset in datasets.iter() {
+                    dataset.write(reset).expect("failure saving file");
+                }
+            }
+            if save || (changed && (edit || external_edit || tag_prompt || new_card)) {
+                //undoing earlier actions no longer brings the data back to the saved state
+                for entry in undostack.iter_mut() {
+                    entry.changed = true;
+                }
+            }
+            if show_forecast {
+                let (width, height) = if minimal.is_none() {
+                    termion::terminal_size().expect("terminal size")
+                } else {
+                    (80, 16)
+                };
+                let forecast = Forecast::new(&datasets, &clock.now(), (height as usize).saturating_sub(3).max(1));
+                draw_lines(&mut stdout, &forecast_lines(&forecast, width as usize), "Press any key to return to the card", minimal);
+                stdin().keys().next();
+            }
         } else if tries > 100 { //after a hundred attempted picks we give up
             write!(stdout, "{}{}{}{}",
                    termion::clear::All,
                    termion::cursor::Goto(1, 5),
-                   "No more cards are due for now, well done! Saving and exiting...",
+                   if datasets.iter().any(|dataset| dataset.session.daily_limit_reached(&now)) {
+                       "The daily limit has been reached, well done! Saving and exiting..."
+                   } else {
+                       "No more cards are due for now, well done! Saving and exiting..."
+                   },
                    termion::cursor::Hide).expect("error drawing");
 
              for dataset in datasets.iter() {
@@ -315,11 +959,272 @@ fn main() {
              done = true;
         }
     }
-    write!(stdout,"{}\n",termion::cursor::Show).expect("error drawing");
+    writeln!(stdout,"{}",termion::cursor::Show).expect("error drawing");
+    if max_cards.is_some() || max_time.is_some() {
+        drop(stdout); //leave raw mode
+        summary.print(&datasets, &clock.now(), &session);
+    }
+}
+
+impl SessionSummary {
+    fn new(datasets: &[VocaData], now: &NaiveDateTime) -> Self {
+        SessionSummary {
+            started: Instant::now(),
+            decks: VocaStats::new(datasets, now).decks,
+            reviewed: 0,
+            promoted: 0,
+            demoted: 0,
+            retained: 0,
+            skipped: 0,
+        }
+    }
+
+    ///Tallies an action taken on a card
+    fn add(&mut self, action: ReviewAction, olddeck: u8, newdeck: u8) {
+        for counter in self.counters(action, olddeck, newdeck) {
+            *counter += 1;
+        }
+    }
+
+    ///Removes an undone action from the tally
+    fn remove(&mut self, action: ReviewAction, olddeck: u8, newdeck: u8) {
+        for counter in self.counters(action, olddeck, newdeck) {
+            *counter = counter.saturating_sub(1);
+        }
+    }
+
+    ///Returns the counters an action counts towards
+    fn counters(&mut self, action: ReviewAction, olddeck: u8, newdeck: u8) -> Vec<&mut usize> {
+        match action {
+            ReviewAction::Skipped => vec!(&mut self.skipped),
+            ReviewAction::Undone(_) => vec!(),
+            ReviewAction::Graded(_) | ReviewAction::Moved => {
+                if newdeck > olddeck {
+                    vec!(&mut self.reviewed, &mut self.promoted)
+                } else if newdeck < olddeck {
+                    vec!(&mut self.reviewed, &mut self.demoted)
+                } else {
+                    vec!(&mut self.reviewed, &mut self.retained)
+                }
+            }
+        }
+    }
+
+    fn print(&self, datasets: &[VocaData], now: &NaiveDateTime, session: &VocaSession) {
+        let elapsed = self.started.elapsed().as_secs();
+        let decks = VocaStats::new(datasets, now).decks;
+        println!("Session summary");
+        println!("Reviewed: {} (promoted: {}, demoted: {}, retained: {})", self.reviewed, self.promoted, self.demoted, self.retained);
+        println!("Skipped: {}", self.skipped);
+        println!("Time spent: {}m {:02}s", elapsed / 60, elapsed % 60);
+        println!("{:<20} {:>8} {:>8}", "Deck", "Before", "After");
+        for (i, (before, after)) in self.decks.iter().zip(decks.iter()).enumerate() {
+            let name = session.decks.get(i).cloned().unwrap_or_else(|| format!("deck {}", i+1));
+            println!("{:<20} {:>8} {:>8}", format!("{}. {}", i+1, name), before, after);
+        }
+    }
+}
+
+
+impl Clock {
+    ///Creates a clock that starts at the time specified with --now, if any, and runs from there
+    fn new(args: &clap::ArgMatches) -> Self {
+        let offset = match args.value_of("now") {
+            Some(now) => match NaiveDateTime::parse_from_str(now.trim(), "%Y-%m-%d %H:%M:%S").or_else(|_| NaiveDate::parse_from_str(now.trim(), "%Y-%m-%d").map(|date| date.and_hms_opt(0, 0, 0).expect("valid time"))) {
+                Ok(now) => now - current_time(),
+                Err(err) => {
+                    eprintln!("ERROR: unable to parse --now, expected YYYY-MM-DD HH:MM:SS: {}", err);
+                    std::process::exit(1);
+                }
+            },
+            None => chrono::Duration::zero(),
+        };
+        Clock { offset }
+    }
+
+    ///Returns the current time according to this clock
+    fn now(&self) -> NaiveDateTime {
+        current_time() + self.offset
+    }
+}
+
+///Argument to shift the current time
+pub fn clock_argument<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("now")
+        .long("now")
+        .takes_value(true)
+        .help("Pretend the current time is this time (YYYY-MM-DD HH:MM:SS or YYYY-MM-DD), e.g. to simulate studying on a future date. The time keeps running from there")
+}
+
+///Arguments for subcommands to specify the vocabulary files to load
+pub fn file_arguments<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec!(
+        Arg::with_name("files")
+            .help("vocabulary file (tsv)")
+            .takes_value(true)
+            .multiple(true)
+            .index(1)
+            .required(true),
+        Arg::with_name("force")
+            .long("force")
+            .short("-f")
+            .help("when loading multiple files, force the metadata of the first one on all the others"),
+    )
+}
+
+///Loads the vocabulary files and configures them according to the command line arguments
+pub fn load(args: &clap::ArgMatches) -> Vec<VocaData> {
+    let mut datasets = load_files(args.values_of("files").unwrap().collect(), args.is_present("force"), args.is_present("reset"), Some(args)).unwrap_or_else(|err| {
+        eprintln!("ERROR: {}", err);
+        std::process::exit(1);
+    });
+    for dataset in datasets.iter_mut() {
+        for warning in dataset.warnings.iter() {
+            eprintln!("WARNING: {}", warning);
+        }
+        if dataset.session.decks.is_empty() && dataset.session.intervals.is_empty() {
+            //no decks or intervals defined yet, set some defaults
+            dataset.session.decks = ["immediate","daily","weekly","monthly","quarterly","yearly"].iter().map(|s| s.to_string()).collect();
+            dataset.session.intervals = vec!(0,1440,10080,43200,129600,518400);
+        }
+    }
+    datasets
 }
 
+///Adds new cards to a vocabulary file (the add subcommand)
+pub fn add(args: &clap::ArgMatches) {
+    let filename = args.value_of("file").unwrap();
+    let mut dataset = match VocaData::from_file(filename, false) {
+        Ok(dataset) => dataset,
+        Err(err) => {
+            eprintln!("ERROR loading {}: {}", filename, err);
+            std::process::exit(1);
+        }
+    };
+    for warning in dataset.warnings.iter() {
+        eprintln!("WARNING: {}", warning);
+    }
+    dataset.expand();
+    let mut cards: Vec<Vec<String>> = Vec::new();
+    if let Some(fields) = args.values_of("fields") {
+        cards.push(fields.map(|field| field.trim().to_owned()).collect());
+    } else if termion::is_tty(&stdin()) {
+        //prompt for each column
+        let mut fields: Vec<String> = Vec::new();
+        for column in dataset.session.columns.iter() {
+            eprint!("{}: ", column);
+            stderr().flush().unwrap();
+            let mut field = String::new();
+            stdin().read_line(&mut field).expect("reading input");
+            fields.push(field.trim().to_owned());
+        }
+        cards.push(fields);
+    } else {
+        for (i, line) in stdin().lock().lines().enumerate() {
+            let line = line.expect("reading input");
+            if !line.trim().is_empty() && !line.starts_with('#') {
+                match VocaCard::parse_line(&line, Some(dataset.session.columns.len()), true, i + 1) {
+                    Ok(card) => cards.push(card.fields),
+                    Err(err) => {
+                        eprintln!("ERROR parsing input: {}", err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+    let mut count = 0;
+    for fields in cards {
+        if fields.iter().any(|field| !field.is_empty()) {
+            dataset.add_card(fields);
+            count += 1;
+        }
+    }
+    if count > 0 {
+        dataset.write(false).expect("failure saving file");
+    }
+    eprintln!("Added {} card(s) to {}", count, filename);
+}
+
+///Lists the cards matching the filter (the list subcommand)
+pub fn list(args: &clap::ArgMatches) {
+    let datasets = load(args);
+    let now = Clock::new(args).now();
+    let filter: Option<CardFilter> = args.value_of("filter").map(|query| {
+        CardFilter::parse(query, &datasets[0].session).unwrap_or_else(|err| {
+            eprintln!("ERROR: {}", err);
+            std::process::exit(1);
+        })
+    });
+    for dataset in datasets.iter() {
+        let session = &dataset.session;
+        for card in dataset.cards.iter() {
+            if filter.as_ref().map(|filter| filter.matches(card, session, &now)).unwrap_or(true) {
+                println!("{}\t{}\t{}\t{}\t{}\t{}",
+                    session.filename().unwrap_or("unnamed"),
+                    session.decks.get(card.deck as usize).unwrap_or(&format!("deck {}", card.deck+1)),
+                    match card.due {
+                        Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+                        None => "unseen".to_owned()
+                    },
+                    match card.state.to_string() {
+                        state if state.is_empty() => "-".to_owned(),
+                        state => state,
+                    },
+                    card.variant.as_deref().unwrap_or("-"),
+                    card.fields.join("\t"),
+                );
+            }
+        }
+    }
+}
+
+///Lists the leeches, most lapses first (the leeches subcommand)
+pub fn leeches(args: &clap::ArgMatches) {
+    let datasets = load(args);
+    let mut leeches: Vec<(&VocaSession, &VocaCard)> = datasets.iter().flat_map(|dataset| {
+        dataset.cards.iter().filter(|card| card.state.leech).map(move |card| (&dataset.session, card))
+    }).collect();
+    leeches.sort_by_key(|(_, card)| std::cmp::Reverse(card.lapses));
+    for (session, card) in leeches {
+        println!("{}\t{}\t{}\t{}\t{}",
+            session.filename().unwrap_or("unnamed"),
+            card.lapses,
+            if card.state.suspended { "suspended" } else { "-" },
+            card.variant.as_deref().unwrap_or("-"),
+            card.fields.join("\t"),
+        );
+    }
+}
+
+///Prints statistics (the stats subcommand)
+pub fn stats(args: &clap::ArgMatches) {
+    let datasets = load(args);
+    let mut stats = VocaStats::new(&datasets, &Clock::new(args).now());
+    let logs: Vec<ReviewLog> = if let Some(filename) = args.value_of("log") {
+        vec!(ReviewLog::new(filename))
+    } else {
+        args.values_of("files").unwrap().map(ReviewLog::sidecar).collect()
+    };
+    for log in logs.iter() {
+        match log.read() {
+            Ok(entries) => stats.add_log(&entries),
+            Err(err) => eprintln!("WARNING: unable to read review log {}: {}", log.filename, err),
+        }
+    }
+    if args.is_present("json") {
+        println!("{}", stats.to_json(&datasets[0].session));
+    } else {
+        let format = match args.value_of("minimal") {
+            Some("plain") => PrintFormat::Plain,
+            _ => PrintFormat::AnsiColour,
+        };
+        stats.print(&datasets[0].session, format);
+    }
+}
 
-pub fn draw(stdout: &mut impl Write, card: Option<&VocaCard>, session: &VocaSession, side: u8, status: &str, seqnr: usize, duecards: usize, minimal: Option<PrintFormat>) {
+#[allow(clippy::too_many_arguments)]
+pub fn draw(stdout: &mut impl Write, card: Option<&VocaCard>, session: &VocaSession, side: u8, status: &str, seqnr: usize, duecards: usize, choices: &[String], minimal: Option<PrintFormat>) {
 
     let mut stdout = AlternateScreen::from(stdout);
 
@@ -346,7 +1251,8 @@ pub fn draw(stdout: &mut impl Write, card: Option<&VocaCard>, session: &VocaSess
     }
 
     if let Some(card) = card {
-        let lines = card.fields_to_str(side, &session, true).expect("printing card failed (no such side?)");
+        let lines = card.fields_to_str(side, session, true).expect("printing card failed (no such side?)");
+        let linecount = lines.len();
         let halftextheight: u16 = (lines.len() / 2) as u16;
         let y = 1 + if height == 0 {
             0 //just so we dont fail in minimal mode
@@ -375,9 +1281,9 @@ pub fn draw(stdout: &mut impl Write, card: Option<&VocaCard>, session: &VocaSess
                    };
             if let Some(minimal) = minimal {
                 if minimal == PrintFormat::AnsiColour {
-                    write!(stdout,"{}{}{}\n",c,line,termion::color::Fg(color::Reset)).expect("error drawing (minimal)");
+                    writeln!(stdout,"{}{}{}",c,line,termion::color::Fg(color::Reset)).expect("error drawing (minimal)");
                 } else {
-                    write!(stdout,"{}\n",line).expect("error drawing (minimal)");
+                    writeln!(stdout,"{}",line).expect("error drawing (minimal)");
                 }
             } else {
                 write!(stdout,"{}{}{}{}{}",
@@ -388,10 +1294,22 @@ pub fn draw(stdout: &mut impl Write, card: Option<&VocaCard>, session: &VocaSess
                    termion::cursor::Hide).expect("error drawing");
             }
         }
+        //multiple choice options
+        let choicewidth = choices.iter().map(|choice| choice.chars().count() + 3).max().unwrap_or(0) as u16;
+        for (i, choice) in choices.iter().enumerate() {
+            if minimal.is_some() {
+                writeln!(stdout,"{}) {}",i+1,choice).expect("error drawing (minimal)");
+            } else {
+                write!(stdout,"{}{}) {}",
+                   termion::cursor::Goto(if width / 2 > choicewidth / 2 { width / 2 - choicewidth / 2 } else { 1 }, y + (linecount + 1 + i) as u16),
+                   i+1,
+                   choice).expect("error drawing");
+            }
+        }
         if minimal.is_none() {
             write!(stdout,"{}{}{}",
                termion::cursor::Goto(1,height),
-               format!("#{}/{} - Deck: {} ({}/{}) - Due: {} ({})",
+               format_args!("#{}/{} - Deck: {} ({}/{}) - Due: {} ({}){}{}",
                     seqnr,
                     duecards,
                     session.decks.get(card.deck as usize).unwrap_or(&"none".to_owned()),
@@ -401,26 +1319,372 @@ pub fn draw(stdout: &mut impl Write, card: Option<&VocaCard>, session: &VocaSess
                         Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
                         None => "any time".to_owned()
                     },
-                    match session.intervals.get(card.deck as usize) {
-                        Some(i) if *i >= 1440 => {
-                            format!("{} days",i/1440)
-                        },
-                        Some(i) if *i >= 60 => {
-                            format!("{} hours",i/60)
-                        },
-                        Some(i) => {
-                            format!("{} mins",i)
-                        },
-                        None => {
-                            "immediate".to_owned()
-                        }
+                    format_interval(card.interval(session)),
+                    match card.tags(session) {
+                        tags if tags.is_empty() => String::new(),
+                        tags => format!(" - Tags: {}", tags.join(", ")),
                     },
+                    if card.state.flagged { " - Flagged" } else { "" },
                ),
                termion::cursor::Hide).expect("error drawing");
         } else {
-            write!(stdout,"\n").expect("error writing");
+            writeln!(stdout).expect("error writing");
+        }
+    }
+
+    stdout.flush().unwrap();
+}
+
+///Browser listing the cards of all datasets in a table, with incremental search (substring or
+///regular expression) and filters on deck and due state. Returns the card selected for review, if any.
+pub fn browse(stdout: &mut impl Write, datasets: &[VocaData], now: &NaiveDateTime, session: &VocaSession) -> Option<(usize,usize)> {
+    let mut stdout = AlternateScreen::from(stdout);
+    let mut query = String::new();
+    let mut use_regex = false;
+    let mut deck: Option<u8> = None;
+    let mut duefilter = DueFilter::All;
+    let mut selected: usize = 0;
+    let mut offset: usize = 0;
+    let mut keys = stdin().keys();
+    loop {
+        let (width, height) = termion::terminal_size().expect("terminal size");
+        let regex = if use_regex && !query.is_empty() {
+            Regex::new(format!("(?i){}", query).as_str()).ok()
+        } else {
+            None
+        };
+        let needle = query.to_lowercase();
+        let rows: Vec<(usize,usize)> = datasets.iter().enumerate()
+            .flat_map(|(setindex, dataset)| dataset.cards.iter().enumerate().map(move |(cardindex, card)| (setindex, cardindex, card)))
+            .filter(|(_, _, card)| deck.is_none_or(|deck| card.deck == deck))
+            .filter(|(_, _, card)| match duefilter {
+                DueFilter::All => true,
+                DueFilter::Due => card.due.is_some_and(|due| due <= *now),
+                DueFilter::NotDue => card.due.is_some_and(|due| due > *now),
+                DueFilter::Unseen => card.due.is_none(),
+            })
+            .filter(|(_, _, card)| {
+                let text = card.fields.join("\t");
+                match (&regex, use_regex) {
+                    _ if query.is_empty() => true,
+                    (Some(regex), _) => regex.is_match(&text),
+                    (None, true) => false, //invalid regular expression
+                    (None, false) => text.to_lowercase().contains(&needle),
+                }
+            })
+            .map(|(setindex, cardindex, _)| (setindex, cardindex))
+            .collect();
+
+        //keep the selection in view
+        let visible = (height as usize).saturating_sub(4).max(1);
+        selected = selected.min(rows.len().saturating_sub(1));
+        if selected < offset {
+            offset = selected;
+        } else if selected >= offset + visible {
+            offset = selected + 1 - visible;
+        }
+
+        //determine the layout of the table
+        let deckwidth = session.decks.iter().map(|deck| deck.width()).max().unwrap_or(0).max(4);
+        let duewidth = 16;
+        let columncount = session.columns.len().max(1);
+        let columnwidth = ((width as usize).saturating_sub(deckwidth + duewidth + 2) / columncount).max(4);
+
+        write!(stdout, "{}{}{}: {}{}{}Deck: {} | Due: {} | {} cards - enter: review, esc: back, tab: deck, ctrl-d: due, ctrl-r: regex",
+               termion::clear::All,
+               termion::cursor::Goto(1, 1),
+               if use_regex { "Search (regex)" } else { "Search" },
+               query,
+               if use_regex && !query.is_empty() && regex.is_none() { " (invalid)" } else { "" },
+               termion::cursor::Goto(1, 2),
+               deck.and_then(|deck| session.decks.get(deck as usize)).map(|deck| deck.as_str()).unwrap_or("all"),
+               match duefilter {
+                   DueFilter::All => "all",
+                   DueFilter::Due => "due",
+                   DueFilter::NotDue => "not due",
+                   DueFilter::Unseen => "unseen",
+               },
+               rows.len()).expect("error drawing");
+        let header: String = session.columns.iter().map(|column| fit(column, columnwidth)).collect::<String>() + fit("deck", deckwidth + 1).as_str() + "due";
+        write!(stdout, "{}{}{}{}", termion::cursor::Goto(1, 3), termion::style::Bold, header, termion::style::Reset).expect("error drawing");
+        for (i, (setindex, cardindex)) in rows.iter().enumerate().skip(offset).take(visible) {
+            let dataset = &datasets[*setindex];
+            let card = &dataset.cards[*cardindex];
+            let mut line: String = (0..session.columns.len())
+                .map(|column| {
+                    let field = card.field_to_str(column as u8, 0, &dataset.session, false).map(|field| field.join(" ")).unwrap_or_default();
+                    fit(&field, columnwidth)
+                })
+                .collect();
+            line += fit(session.decks.get(card.deck as usize).map(|deck| deck.as_str()).unwrap_or("none"), deckwidth + 1).as_str();
+            line += card.due.map(|due| due.format("%Y-%m-%d %H:%M").to_string()).unwrap_or_else(|| "unseen".to_owned()).as_str();
+            write!(stdout, "{}{}{}{}",
+                   termion::cursor::Goto(1, 4 + (i - offset) as u16),
+                   if i == selected { format!("{}", termion::style::Invert) } else { String::new() },
+                   line,
+                   termion::style::Reset).expect("error drawing");
+        }
+        write!(stdout, "{}", termion::cursor::Hide).expect("error drawing");
+        stdout.flush().unwrap();
+
+        match keys.next() {
+            Some(Ok(Key::Esc)) | None => return None,
+            Some(Ok(Key::Char('\n'))) => {
+                if let Some(row) = rows.get(selected) {
+                    return Some(*row);
+                }
+            },
+            Some(Ok(Key::Up)) => selected = selected.saturating_sub(1),
+            Some(Ok(Key::Down)) => selected += 1,
+            Some(Ok(Key::PageUp)) => selected = selected.saturating_sub(visible),
+            Some(Ok(Key::PageDown)) => selected += visible,
+            Some(Ok(Key::Char('\t'))) => {
+                deck = match deck {
+                    None if !session.decks.is_empty() => Some(0),
+                    Some(deck) if (deck as usize) + 1 < session.decks.len() => Some(deck + 1),
+                    _ => None,
+                };
+            },
+            Some(Ok(Key::BackTab)) => {
+                deck = match deck {
+                    None if !session.decks.is_empty() => Some(session.decks.len() as u8 - 1),
+                    Some(deck) if deck > 0 => Some(deck - 1),
+                    _ => None,
+                };
+            },
+            Some(Ok(Key::Ctrl('d'))) => {
+                duefilter = match duefilter {
+                    DueFilter::All => DueFilter::Due,
+                    DueFilter::Due => DueFilter::NotDue,
+                    DueFilter::NotDue => DueFilter::Unseen,
+                    DueFilter::Unseen => DueFilter::All,
+                };
+            },
+            Some(Ok(Key::Ctrl('r'))) => use_regex = !use_regex,
+            Some(Ok(Key::Backspace)) => {
+                query.pop();
+                selected = 0;
+            },
+            Some(Ok(Key::Char(c))) if !c.is_control() => {
+                query.push(c);
+                selected = 0;
+            },
+            _ => {},
+        }
+    }
+}
+
+///Truncates or pads a text to exactly the specified width (in terminal columns, wide characters take two), leaving at least one space at the end
+fn fit(text: &str, width: usize) -> String {
+    let mut result = String::new();
+    let mut length = 0;
+    for c in text.chars() {
+        let charwidth = c.width().unwrap_or(0);
+        if length + charwidth >= width {
+            break;
+        }
+        result.push(c);
+        length += charwidth;
+    }
+    result.extend(std::iter::repeat_n(' ', width.saturating_sub(length)));
+    result
+}
+
+///Updates the line of a card (e.g. replaces the fields of the card and its siblings) with the specified function, which returns
+///the new index of the card. This may change the number of cards derived from the line (e.g. when clozes are added or removed),
+///so the card indices remembered in the history, the presented siblings and the undo stack are adjusted. Returns the new index of the card.
+fn update_line(dataset: &mut VocaData, setindex: usize, cardindex: usize, update: impl FnOnce(&mut VocaData) -> usize, history: &mut [(usize,usize)], siblings: &mut HashSet<usize>, undostack: &mut Vec<UndoEntry>) -> usize {
+    let line = dataset.line(cardindex);
+    let before = dataset.cards.len();
+    let newindex = update(dataset);
+    let after = dataset.cards.len();
+    if after != before {
+        let reindex = |index: usize| {
+            if index >= line.end {
+                index + after - before
+            } else if index >= line.start {
+                newindex
+            } else {
+                index
+            }
+        };
+        for entry in history.iter_mut().filter(|entry| entry.0 == setindex) {
+            entry.1 = reindex(entry.1);
+        }
+        //the state of the other variants of the line can no longer be restored reliably
+        undostack.retain(|entry| entry.setindex != setindex || entry.cardindex == cardindex || !line.contains(&entry.cardindex));
+        for entry in undostack.iter_mut().filter(|entry| entry.setindex == setindex) {
+            entry.cardindex = reindex(entry.cardindex);
+        }
+        *siblings = siblings.iter().filter(|index| !line.contains(index)).map(|index| reindex(*index)).chain(dataset.siblings(newindex)).collect();
+    }
+    newindex
+}
+
+///Inline editor for the fields of a card, labelled with the column names. Returns the edited
+///fields, or None if editing was cancelled.
+pub fn edit_fields(stdout: &mut impl Write, fields: &[String], columns: &[String]) -> Option<Vec<String>> {
+    let mut stdout = AlternateScreen::from(stdout);
+    let count = fields.len().max(columns.len()).max(1);
+    let labels: Vec<String> = (0..count).map(|i| columns.get(i).cloned().unwrap_or_else(|| format!("column#{}", i+1))).collect();
+    let labelwidth = labels.iter().map(|label| label.chars().count()).max().unwrap_or(0);
+    //fields are edited as sequences of characters, so the cursor always moves over whole characters
+    let mut values: Vec<Vec<char>> = (0..count).map(|i| fields.get(i).map(|field| field.chars().collect()).unwrap_or_default()).collect();
+    let mut current: usize = 0;
+    let mut cursor: usize = values[0].len();
+    let mut keys = stdin().keys();
+    loop {
+        write!(stdout, "{}{}Editing card - enter: save, esc: cancel, tab/↑/↓: other field",
+               termion::clear::All,
+               termion::cursor::Goto(1, 1)).expect("error drawing");
+        for (i, (label, value)) in labels.iter().zip(values.iter()).enumerate() {
+            write!(stdout, "{}{}{:>width$}{}: {}",
+                   termion::cursor::Goto(1, 3 + i as u16),
+                   if i == current { color::Fg(&color::Green as &dyn color::Color) } else { color::Fg(&color::Reset as &dyn color::Color) },
+                   label,
+                   color::Fg(color::Reset),
+                   value.iter().collect::<String>(),
+                   width = labelwidth).expect("error drawing");
+        }
+        //position the cursor by display width, as some characters (e.g. CJK) take up two columns
+        let label = &labels[current];
+        let column = labelwidth - label.chars().count() + label.width() + 2 + values[current][..cursor].iter().map(|c| c.width().unwrap_or(0)).sum::<usize>();
+        write!(stdout, "{}{}",
+               termion::cursor::Goto((column + 1) as u16, 3 + current as u16),
+               termion::cursor::Show).expect("error drawing");
+        stdout.flush().unwrap();
+        match keys.next() {
+            Some(Ok(Key::Char('\n'))) => break,
+            Some(Ok(Key::Esc)) | None => {
+                write!(stdout, "{}", termion::cursor::Hide).expect("error drawing");
+                return None;
+            },
+            Some(Ok(Key::Char('\t'))) | Some(Ok(Key::Down)) => {
+                current = (current + 1) % count;
+                cursor = values[current].len();
+            },
+            Some(Ok(Key::BackTab)) | Some(Ok(Key::Up)) => {
+                current = (current + count - 1) % count;
+                cursor = values[current].len();
+            },
+            Some(Ok(Key::Left)) => cursor = cursor.saturating_sub(1),
+            Some(Ok(Key::Right)) => cursor = (cursor + 1).min(values[current].len()),
+            Some(Ok(Key::Home)) | Some(Ok(Key::Ctrl('a'))) => cursor = 0,
+            Some(Ok(Key::End)) | Some(Ok(Key::Ctrl('e'))) => cursor = values[current].len(),
+            Some(Ok(Key::Backspace)) if cursor > 0 => {
+                cursor -= 1;
+                values[current].remove(cursor);
+            },
+            Some(Ok(Key::Delete)) if cursor < values[current].len() => {
+                values[current].remove(cursor);
+            },
+            Some(Ok(Key::Char(c))) if !c.is_control() => {
+                values[current].insert(cursor, c);
+                cursor += 1;
+            },
+            _ => {},
         }
     }
+    write!(stdout, "{}", termion::cursor::Hide).expect("error drawing");
+    Some(values.into_iter().map(|value| value.into_iter().collect::<String>().trim().to_owned()).collect())
+}
 
+///Opens a line of a vocabulary file (a card) in the user's editor ($EDITOR), returns the edited
+///line, or None if it was removed
+pub fn edit_line(line: &str) -> Result<Option<String>, std::io::Error> {
+    //a new file with an unpredictable name, created exclusively so no existing file (or symlink) is ever written to
+    let (filename, mut file) = loop {
+        let filename = std::env::temp_dir().join(format!("vocage-{}-{:016x}.tsv", std::process::id(), rand::random::<u64>()));
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&filename) {
+            Ok(file) => break (filename, file),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    };
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_owned());
+    let contents = file.write_all(format!("{}\n", line).as_bytes()).and_then(|_| {
+        drop(file);
+        let status = std::process::Command::new(&editor).arg(&filename).status()?;
+        if !status.success() {
+            return Err(std::io::Error::other(format!("{} exited with an error", editor)));
+        }
+        std::fs::read_to_string(&filename)
+    });
+    //the file is removed whether editing succeeded or not
+    let removed = std::fs::remove_file(&filename);
+    let contents = contents?;
+    removed?;
+    Ok(contents.lines().find(|line| !line.is_empty() && !line.starts_with('#')).map(|line| line.to_owned()))
+}
+
+///Formats an interval (in minutes) for display
+pub fn format_interval(interval: Option<u32>) -> String {
+    match interval {
+        Some(i) if i >= 1440 => {
+            format!("{} days",i/1440)
+        },
+        Some(i) if i >= 60 => {
+            format!("{} hours",i/60)
+        },
+        Some(i) => {
+            format!("{} mins",i)
+        },
+        None => {
+            "immediate".to_owned()
+        }
+    }
+}
+
+///Composes the status line; in grading mode this shows the next interval for each grade
+pub fn status_line(status: &str, graded: bool, card: &VocaCard, scheduler: &dyn Scheduler, now: &NaiveDateTime, session: &VocaSession) -> String {
+    if !graded {
+        return status.to_owned();
+    }
+    let grades: Vec<String> = GRADES.iter().map(|(key, grade)| {
+        let interval = card.preview(*grade, scheduler, now, session).due.map(|due| (due - *now).num_minutes().max(0) as u32);
+        format!("{}:{} ({})", key, grade, format_interval(interval))
+    }).collect();
+    if status.is_empty() {
+        grades.join("  ")
+    } else {
+        format!("{} | {}", status, grades.join("  "))
+    }
+}
+
+///Renders a forecast as a histogram, one line per day
+pub fn forecast_lines(forecast: &Forecast, width: usize) -> Vec<String> {
+    let max = forecast.reviews.iter().chain(forecast.projected.iter()).copied().max().unwrap_or(0).max(1);
+    let barwidth = (width.saturating_sub(31) / 2).max(1);
+    let bar = |count: usize| "█".repeat((count * barwidth).div_ceil(max));
+    let mut lines = vec!(format!("{:<14} {:<w$}  {}", "", "Due", "Due if all cards due today are answered right", w = barwidth + 6));
+    for (day, (reviews, projected)) in forecast.reviews.iter().zip(forecast.projected.iter()).enumerate() {
+        lines.push(format!("{} {:>5} {:<w$}  {:>5} {}",
+            forecast.date(day).format("%a %Y-%m-%d"),
+            reviews,
+            bar(*reviews),
+            projected,
+            bar(*projected),
+            w = barwidth));
+    }
+    lines
+}
+
+///Draws a screen of text lines (rather than a card)
+pub fn draw_lines(stdout: &mut impl Write, lines: &[String], status: &str, minimal: Option<PrintFormat>) {
+    if minimal.is_some() {
+        for line in lines.iter() {
+            writeln!(stdout, "{}", line).expect("error drawing (minimal)");
+        }
+        return;
+    }
+    let mut stdout = AlternateScreen::from(stdout);
+    write!(stdout, "{}{}{}{}",
+           termion::clear::All,
+           termion::cursor::Goto(1, 1),
+           status,
+           termion::cursor::Hide).expect("error drawing");
+    for (i, line) in lines.iter().enumerate() {
+        write!(stdout, "{}{}", termion::cursor::Goto(1, 3 + i as u16), line).expect("error drawing");
+    }
     stdout.flush().unwrap();
 }
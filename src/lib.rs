@@ -4,16 +4,38 @@ extern crate clap;
 extern crate rand;
 
 use ansi_term::Colour;
-use chrono::NaiveDateTime;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc};
 use clap::{App, Arg};
-use rand::prelude::Rng;
+use rand::prelude::{Rng, SliceRandom};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind, Write};
-use std::path::PathBuf;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
 
-#[derive(Clone)]
+///Errors that may occur when loading or configuring vocabulary data
+#[derive(Debug)]
+pub enum VocaError {
+    ///Reading a file failed
+    Io(std::io::Error),
+    ///A line could not be parsed, in the file (if known) and on the line specified
+    Parse {
+        filename: Option<String>,
+        line: usize,
+        message: String,
+    },
+    ///A column is referenced that is not defined
+    UnknownColumn(String),
+    ///The number of decks differs from the number of intervals
+    DeckIntervalMismatch { decks: usize, intervals: usize },
+    ///A file can not be loaded together with the files loaded before it
+    IncompatibleDatasets { filename: String, reason: String },
+    ///A parameter (or metadata in a file) has an invalid value
+    InvalidArgument(String),
+}
+
+#[derive(Clone, Default)]
 pub struct VocaSession {
     pub columns: Vec<String>,
     pub decks: Vec<String>,
@@ -25,6 +47,29 @@ pub struct VocaSession {
     pub showcolumns: Vec<Vec<u8>>,
     ///list delimiter
     pub listdelimiter: Option<String>,
+    ///Spaced repetition algorithm to use
+    pub schedulertype: SchedulerType,
+    ///Target retention rate for the FSRS scheduler (defaults to 0.9)
+    pub retention: Option<f64>,
+    ///Weights (model parameters) for the FSRS scheduler (defaults are used if empty)
+    pub weights: Vec<f64>,
+    ///Column holding cloze deletions, each cloze in it yields a separate (virtual) card
+    pub clozecolumn: Option<u8>,
+    ///Delimiter between the tags in the ``tags`` column (defaults to a space)
+    pub tagdelimiter: Option<String>,
+    ///Number of lapses after which a card is marked as a leech
+    pub leechthreshold: Option<u32>,
+    ///Suspend cards automatically when they are marked as a leech
+    pub leechsuspend: bool,
+    ///Maximum number of unseen cards to introduce per day
+    pub newperday: Option<u32>,
+    ///Maximum number of reviews of previously seen cards per day
+    pub reviewsperday: Option<u32>,
+    ///Number of new cards and reviews studied on the most recent day of study
+    pub studied: DailyCounts,
+    ///Directions in which cards are drilled, each is an order of sides (indices into ``showcolumns``).
+    ///If there are multiple, each direction yields a separate (virtual) card.
+    pub directions: Vec<Vec<u8>>,
     header: bool,
 }
 
@@ -32,65 +77,516 @@ pub struct VocaData {
     pub session: VocaSession,
     pub cards: Vec<VocaCard>,
     pub comments: Vec<(usize, String)>,
+    ///Problems in individual lines that did not prevent loading, e.g. special columns with an invalid value
+    pub warnings: Vec<VocaError>,
 }
 
 pub struct VocaCard {
     pub fields: Vec<String>,
     pub due: Option<NaiveDateTime>,
     pub deck: u8,
+    ///State for the SM-2 scheduler (only set once the card has been reviewed using SM-2)
+    pub sm2: Option<Sm2State>,
+    ///State for the FSRS scheduler (only set once the card has been reviewed using FSRS)
+    pub fsrs: Option<FsrsState>,
+    ///Stable identifier of the card, serialised in the ``id@`` column
+    pub id: Option<String>,
+    ///Whether the card is suspended, buried or flagged
+    pub state: CardState,
+    ///Number of times the card dropped to a lower deck, serialised in the ``lapses@`` column
+    pub lapses: u32,
+    ///The cloze this card asks for, if it is a cloze deletion card
+    pub cloze: Option<Cloze>,
+    ///The direction in which this card is drilled (index into ``VocaSession::directions``)
+    pub direction: u8,
+    ///Label of the variant if this is a virtual card derived from the same line as the preceding
+    ///card (e.g. ``c2`` for the second cloze, ``d2`` for the second direction), its state is
+    ///serialised with this label as prefix
+    pub variant: Option<String>,
+    ///Scheduling state of variants found in the line that do not (or no longer) exist, retained
+    ///so it is written back unaltered
+    pub variantstates: Vec<VariantState>,
+}
+
+///A cloze deletion: the gap(s) with the specified number in the specified column are blanked
+///on the front side of the card
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cloze {
+    pub column: u8,
+    pub number: u8,
+}
+
+///Scheduling state of a variant of a card, serialised in the line of the card with the label as prefix (e.g. ``c2:deck#3``)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VariantState {
+    pub label: String,
+    pub schedule: Schedule,
+    pub id: Option<String>,
+    pub state: CardState,
+    pub lapses: u32,
+}
+
+///Number of new cards and reviews studied on a day, used to enforce the daily limits. Serialised in
+///the ``#--studied`` metadata (e.g. ``#--studied 2021-03-01,20,150``)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct DailyCounts {
+    pub date: Option<NaiveDate>,
+    ///Number of unseen cards that were studied
+    pub new: u32,
+    ///Number of reviews of previously seen cards
+    pub reviews: u32,
 }
 
+///State of a card that determines whether it is presented at all, serialised in the ``state@`` column
+///(e.g. ``state@suspended,flagged``)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct CardState {
+    ///The card is never presented
+    pub suspended: bool,
+    ///The card is marked for later attention (e.g. to edit it), this does not affect scheduling
+    pub flagged: bool,
+    ///The card is not presented before this date
+    pub buried: Option<NaiveDate>,
+    ///The card lapsed (dropped to a lower deck) too often, see ``VocaSession::leechthreshold``
+    pub leech: bool,
+}
+
+///A gap in a field with cloze deletions, either ``{{c1::answer}}``, ``{{c1::answer::hint}}`` or ``[answer]``
+struct ClozeGap<'a> {
+    ///Byte offsets of the markup in the field
+    begin: usize,
+    end: usize,
+    number: u8,
+    answer: &'a str,
+    hint: Option<&'a str>,
+}
+
+///Prefixes of the special columns holding the state of a card
+const STATE_PREFIXES: [&str; 11] = [
+    "deck#",
+    "due@",
+    "ease@",
+    "interval@",
+    "reps@",
+    "stability@",
+    "difficulty@",
+    "last@",
+    "id@",
+    "state@",
+    "lapses@",
+];
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PrintFormat {
     Plain,
     AnsiColour,
 }
 
+///Grade given to a card when it is reviewed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Grade {
+    ///The answer was wrong
+    Again,
+    ///The answer was right, but with considerable effort
+    Hard,
+    ///The answer was right
+    Good,
+    ///The answer was right and effortless
+    Easy,
+}
+
+///Scheduling state of a card
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Schedule {
+    pub deck: u8,
+    pub due: Option<NaiveDateTime>,
+    pub sm2: Option<Sm2State>,
+    pub fsrs: Option<FsrsState>,
+}
+
+///The available spaced repetition algorithms
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SchedulerType {
+    #[default]
+    Leitner,
+    Sm2,
+    Fsrs,
+}
+
+///Per-card state of the SM-2 algorithm, serialised in the ``ease@``, ``interval@`` and ``reps@`` columns
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sm2State {
+    ///Ease factor, determines how fast the interval grows (minimum 1.3)
+    pub ease: f64,
+    ///Current interval in days
+    pub interval: u32,
+    ///Number of consecutive correct repetitions
+    pub repetitions: u32,
+}
+
+///Per-card state of the FSRS algorithm, serialised in the ``stability@``, ``difficulty@`` and ``last@`` columns
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct FsrsState {
+    ///Stability in days: the interval after which the probability of recall drops to 90%
+    pub stability: f64,
+    ///Difficulty of the card, from 1 (easiest) to 10 (hardest)
+    pub difficulty: f64,
+    ///Time of the last review
+    pub last: Option<NaiveDateTime>,
+}
+
+///A spaced repetition algorithm, determines when a card is to be presented again
+pub trait Scheduler {
+    ///Computes the new scheduling state of a card that was given the specified grade at the specified time
+    fn schedule(
+        &self,
+        card: &VocaCard,
+        grade: Grade,
+        now: &NaiveDateTime,
+        session: &VocaSession,
+    ) -> Schedule;
+}
+
+///The Leitner system: cards move up a deck when answered right and down a deck when answered
+///wrong, each deck has a fixed interval (see ``VocaSession::intervals``)
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Leitner;
+
+///The SuperMemo 2 algorithm: each card has its own ease factor that determines how fast its
+///interval grows. Decks are still assigned as in the Leitner system, for reference only.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Sm2;
+
+///The Free Spaced Repetition Scheduler (FSRS v4.5): models the stability and difficulty of each
+///card and schedules it when the probability of recall is expected to drop to the target retention
+///rate. Decks are still assigned as in the Leitner system, for reference only.
+#[derive(Debug, Clone)]
+pub struct Fsrs {
+    ///Target retention rate (probability of recall)
+    pub retention: f64,
+    ///Model parameters (17 weights)
+    pub weights: Vec<f64>,
+}
+
+///Default FSRS v4.5 weights
+pub const FSRS_DEFAULT_WEIGHTS: [f64; 17] = [
+    0.4872, 1.4003, 3.7145, 13.8206, 5.1618, 1.2298, 0.8975, 0.031, 1.6474, 0.1367, 1.0461, 2.1072,
+    0.0793, 0.3246, 1.587, 0.2272, 2.8755,
+];
+const FSRS_DECAY: f64 = -0.5;
+const FSRS_FACTOR: f64 = 19.0 / 81.0;
+
+///An action taken on a card during a review session
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ReviewAction {
+    ///The card was graded and rescheduled accordingly
+    Graded(Grade),
+    ///The card was moved to a specific deck
+    Moved,
+    ///The card was skipped
+    Skipped,
+    ///The grade or move logged for the card at the referenced time (or, if no time is referenced, the last one
+    ///logged for the card) was undone, the card is back on the new deck of this entry
+    Undone(Option<NaiveDateTime>),
+}
+
+///A single entry in a review log
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReviewLogEntry {
+    pub timestamp: NaiveDateTime,
+    ///Identifies the card that was reviewed
+    pub card: String,
+    ///Deck the card was on before the action (0-indexed)
+    pub olddeck: u8,
+    ///Deck the card is on after the action (0-indexed)
+    pub newdeck: u8,
+    pub action: ReviewAction,
+    ///Time taken to answer, in milliseconds
+    pub duration: u64,
+    ///The side of the card that was shown when the action was taken
+    pub side: u8,
+}
+
+///An append-only log of all review actions, stored as TSV
+#[derive(Debug, Clone)]
+pub struct ReviewLog {
+    pub filename: String,
+}
+
+///Statistics summarising the learning progress over one or more datasets
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VocaStats {
+    ///Total number of cards
+    pub total: usize,
+    ///Number of cards per deck
+    pub decks: Vec<usize>,
+    ///Number of cards that have never been presented
+    pub unseen: usize,
+    ///Number of cards that have been presented before
+    pub seen: usize,
+    ///Number of suspended cards (these are not counted as due)
+    pub suspended: usize,
+    ///Number of flagged cards
+    pub flagged: usize,
+    ///Number of cards marked as a leech
+    pub leeches: usize,
+    ///Number of seen cards that are due now
+    pub overdue: usize,
+    ///Number of seen cards that will become due within the next day
+    pub due_day: usize,
+    ///Number of seen cards that will become due within the next week
+    pub due_week: usize,
+    ///Number of seen cards that will become due within the next month (30 days)
+    pub due_month: usize,
+    ///Number of graded reviews in the review log(s)
+    pub reviews: usize,
+    ///Number of graded reviews of previously seen cards that were answered right
+    pub recalled: usize,
+    ///Number of graded reviews of previously seen cards
+    pub recallable: usize,
+}
+
+///Outcome of checking a typed answer against a card
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnswerCheck {
+    ///Suggested grade: good for a correct answer, hard for an answer that is only off by accents or a typo, again otherwise
+    pub grade: Grade,
+    ///The expected answer (or alternative) that came closest to the typed answer
+    pub expected: String,
+    ///Edit distance between the typed answer and the expected answer, after normalisation
+    pub distance: usize,
+}
+
+///A filter on cards, parsed from a query such as ``deck:>=3 due:<7d POS=noun -unseen``. A card
+///matches if it matches all terms, terms prefixed with ``-`` are negated.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CardFilter {
+    ///The terms of the filter, along with whether they are negated
+    pub terms: Vec<(bool, FilterTerm)>,
+}
+
+///Constraints on the cards that may be picked for presentation
+#[derive(Debug, Copy, Clone, Default)]
+pub struct PickOptions<'a> {
+    ///Only pick cards on these decks
+    pub decks: Option<&'a Vec<u8>>,
+    ///Only pick cards that are due
+    pub due_only: bool,
+    ///Only pick cards that have been presented before
+    pub seen_only: bool,
+    ///Indices of cards not to pick
+    pub exclude: Option<&'a HashSet<usize>>,
+    ///Only pick cards matching this filter
+    pub filter: Option<&'a CardFilter>,
+}
+
+///A single term of a card filter
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterTerm {
+    ///``deck:N``, ``deck:>=N``, ``deck:name``: the (0-indexed) deck the card is on
+    Deck(Comparison, u8),
+    ///``due:<7d``: the time until the card is due, in minutes (negative if overdue), never matches unseen cards
+    Due(Comparison, i64),
+    ///``COLUMN=value``: the field equals the value (or one of its list items), case-insensitive
+    FieldEquals(u8, String),
+    ///``COLUMN~value``: the field contains the value, case-insensitive
+    FieldContains(u8, String),
+    ///``tag:hsk1,hsk2``: the card has any of the tags
+    Tags(Vec<String>),
+    ///``due``: the card is due now (unseen cards are always due)
+    DueNow,
+    ///``seen``: the card has been presented before
+    Seen,
+    ///``unseen``: the card has never been presented
+    Unseen,
+    ///``suspended``: the card is suspended
+    Suspended,
+    ///``buried``: the card is buried
+    Buried,
+    ///``flagged``: the card is flagged
+    Flagged,
+    ///``leech``: the card is a leech
+    Leech,
+}
+
+///Comparison operator in a card filter
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+///Forecast of the number of reviews due on each of the upcoming days
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Forecast {
+    ///First day of the forecast (today)
+    pub start: NaiveDate,
+    ///Number of reviews due per day, the first day includes all overdue cards
+    pub reviews: Vec<usize>,
+    ///Projected number of reviews per day if every card due today were answered right
+    pub projected: Vec<usize>,
+}
+
+impl VocaError {
+    ///Creates a parse error for the specified line
+    pub fn parse(line: usize, message: String) -> Self {
+        VocaError::Parse {
+            filename: None,
+            line,
+            message,
+        }
+    }
+
+    ///Associates a parse error with the file it occurred in
+    pub fn in_file(self, filename: &str) -> Self {
+        match self {
+            VocaError::Parse { line, message, .. } => VocaError::Parse {
+                filename: Some(filename.to_owned()),
+                line,
+                message,
+            },
+            error => error,
+        }
+    }
+}
+
+impl fmt::Display for VocaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VocaError::Io(error) => write!(f, "{}", error),
+            VocaError::Parse {
+                filename: Some(filename),
+                line,
+                message,
+            } => write!(f, "{}, line {}: {}", filename, line, message),
+            VocaError::Parse {
+                filename: None,
+                line,
+                message,
+            } => write!(f, "line {}: {}", line, message),
+            VocaError::UnknownColumn(column) => write!(f, "no such column: {}", column),
+            VocaError::DeckIntervalMismatch { decks, intervals } => write!(
+                f,
+                "intervals and decks have different length ({} decks, {} intervals)",
+                decks, intervals
+            ),
+            VocaError::IncompatibleDatasets { filename, reason } => {
+                write!(
+                    f,
+                    "unable to load {} together with the other files: {}",
+                    filename, reason
+                )
+            }
+            VocaError::InvalidArgument(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for VocaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            VocaError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for VocaError {
+    fn from(error: std::io::Error) -> Self {
+        VocaError::Io(error)
+    }
+}
+
 impl VocaSession {
     pub fn common_arguments<'a, 'b>() -> Vec<clap::Arg<'a, 'b>> {
-        let mut args: Vec<Arg> = Vec::new();
-        args.push( Arg::with_name("showcolumns")
-            .long("showcolumns")
-            .short("-C")
-            .help("Specify what columns to show on the card, comma separated list of column names, specify this multiple times to define multiple 'sides' of the card. The columns themselves are defined using --columns")
-            .multiple(true)
-            .takes_value(true)
-        );
-        args.push(
+        vec![
+            Arg::with_name("showcolumns")
+                .long("showcolumns")
+                .short("-C")
+                .help("Specify what columns to show on the card, comma separated list of column names, specify this multiple times to define multiple 'sides' of the card. The columns themselves are defined using --columns")
+                .multiple(true)
+                .takes_value(true),
             Arg::with_name("decks")
                 .long("decks")
                 .short("-d")
                 .help("Comma seperated list of deck names")
                 .takes_value(true),
-        );
-        args.push( Arg::with_name("intervals")
-            .long("intervals")
-            .short("-i")
-            .help("Comma seperated list of intervals for each respective deck (in minutes). Must contain as many items as --decks")
-            .takes_value(true)
-        );
-        args.push(
+            Arg::with_name("intervals")
+                .long("intervals")
+                .short("-i")
+                .help("Comma seperated list of intervals for each respective deck (in minutes). Must contain as many items as --decks")
+                .takes_value(true),
             Arg::with_name("columns")
                 .long("columns")
                 .short("-c")
                 .help("Comma separated list of column names.")
                 .takes_value(true),
-        );
-        args.push( Arg::with_name("listdelimiter")
-            .long("listdelimiter")
-            .short("-l")
-            .help("List delimiter to separate multiple alternatives within a field (recommended: | )")
-            .takes_value(true)
-        );
-        args.push( Arg::with_name("returntofirst")
-            .long("returntofirst")
-            .short("-1")
-            .help("When a card is demoted (e.g. answered incorrectly), demote it to the very first deck rather than the previous deck")
-        );
-        args
+            Arg::with_name("listdelimiter")
+                .long("listdelimiter")
+                .short("-l")
+                .help("List delimiter to separate multiple alternatives within a field (recommended: | )")
+                .takes_value(true),
+            Arg::with_name("returntofirst")
+                .long("returntofirst")
+                .short("-1")
+                .help("When a card is demoted (e.g. answered incorrectly), demote it to the very first deck rather than the previous deck"),
+            Arg::with_name("scheduler")
+                .long("scheduler")
+                .help("Spaced repetition algorithm: leitner (fixed interval per deck, default), sm2 (SuperMemo 2, interval grows with a per-card ease factor) or fsrs (Free Spaced Repetition Scheduler, models per-card stability and difficulty)")
+                .possible_values(&["leitner", "sm2", "fsrs"])
+                .takes_value(true),
+            Arg::with_name("retention")
+                .long("retention")
+                .help("Target retention rate for the fsrs scheduler, i.e. the desired probability of recalling a card when it is due (default: 0.9)")
+                .takes_value(true),
+            Arg::with_name("weights")
+                .long("weights")
+                .help("Comma separated list of the 17 model weights for the fsrs scheduler (defaults will be used if not specified)")
+                .takes_value(true),
+            Arg::with_name("direction")
+                .long("direction")
+                .help("Comma separated list of side numbers (1-based, referring to --showcolumns) in the order they are to be shown. Specify this multiple times to drill cards in multiple directions (e.g. 1,2 and 2,1), each direction is scheduled independently")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+            Arg::with_name("tagdelimiter")
+                .long("tagdelimiter")
+                .help("Delimiter between the tags in the column named 'tags' (default: a space)")
+                .takes_value(true),
+            Arg::with_name("leechthreshold")
+                .long("leechthreshold")
+                .help("Mark cards as a leech once they have lapsed (dropped to a lower deck) this many times")
+                .takes_value(true),
+            Arg::with_name("leechsuspend")
+                .long("leechsuspend")
+                .help("Automatically suspend cards when they are marked as a leech (see --leechthreshold)"),
+            Arg::with_name("new-per-day")
+                .long("new-per-day")
+                .help("Maximum number of new (unseen) cards to introduce per day")
+                .takes_value(true),
+            Arg::with_name("reviews-per-day")
+                .long("reviews-per-day")
+                .help("Maximum number of reviews of previously seen cards per day")
+                .takes_value(true),
+            Arg::with_name("studied")
+                .long("studied")
+                .help("Number of new cards and reviews studied on a day (date,new,reviews), kept as metadata to enforce the daily limits")
+                .hidden(true)
+                .takes_value(true),
+            Arg::with_name("cloze")
+                .long("cloze")
+                .help("Name of the column holding cloze deletions, marked as {{c1::answer}}, {{c1::answer::hint}} or [answer]. Each cloze yields a separate card that shows the field with the gap blanked on the front side")
+                .takes_value(true),
+        ]
     }
 
-    pub fn set_common_arguments<'a>(&mut self, args: &clap::ArgMatches<'a>) -> Result<(), Error> {
+    pub fn set_common_arguments<'a>(
+        &mut self,
+        args: &clap::ArgMatches<'a>,
+    ) -> Result<(), VocaError> {
         if let Some(decks) = args.value_of("decks") {
             self.decks = decks
                 .trim()
@@ -102,8 +598,12 @@ impl VocaSession {
             self.intervals = intervals
                 .trim()
                 .split(",")
-                .map(|s| s.parse::<u32>().expect("parsing interval"))
-                .collect();
+                .map(|s| {
+                    s.trim()
+                        .parse::<u32>()
+                        .map_err(|_| VocaError::InvalidArgument(format!("invalid interval: {}", s)))
+                })
+                .collect::<Result<Vec<u32>, VocaError>>()?;
         }
         if let Some(columns) = args.value_of("columns") {
             self.columns = columns
@@ -125,55 +625,270 @@ impl VocaSession {
                         .map(|s| {
                             self.columns
                                 .iter()
-                                .enumerate()
-                                .find(|&r| r.1 == s.trim())
-                                .expect(
-                                    format!(
-                                        "ERROR: showcolumns references a non-existing column: {}",
-                                        s
-                                    )
-                                    .as_str(),
-                                )
-                                .0
+                                .position(|column| column == s.trim())
+                                .map(|n| n as u8)
+                                .ok_or_else(|| VocaError::UnknownColumn(s.trim().to_owned()))
                         })
-                        .map(|n| n as u8)
-                        .collect(),
+                        .collect::<Result<Vec<u8>, VocaError>>()?,
                 );
             }
         }
         if args.is_present("returntofirst") {
             self.returntofirst = true;
         }
+        if let Some(tagdelimiter) = args.value_of("tagdelimiter") {
+            self.tagdelimiter = Some(tagdelimiter.to_string());
+        }
+        if let Some(leechthreshold) = args.value_of("leechthreshold") {
+            match leechthreshold.trim().parse::<u32>() {
+                Ok(leechthreshold) if leechthreshold > 0 => {
+                    self.leechthreshold = Some(leechthreshold)
+                }
+                _ => {
+                    return Err(VocaError::InvalidArgument(
+                        "leechthreshold must be a positive integer".to_owned(),
+                    ))
+                }
+            }
+        }
+        if args.is_present("leechsuspend") {
+            self.leechsuspend = true;
+        }
+        if let Some(newperday) = args.value_of("new-per-day") {
+            self.newperday = Some(newperday.trim().parse::<u32>().map_err(|_| {
+                VocaError::InvalidArgument("new-per-day must be an integer".to_owned())
+            })?);
+        }
+        if let Some(reviewsperday) = args.value_of("reviews-per-day") {
+            self.reviewsperday = Some(reviewsperday.trim().parse::<u32>().map_err(|_| {
+                VocaError::InvalidArgument("reviews-per-day must be an integer".to_owned())
+            })?);
+        }
+        if let Some(studied) = args.value_of("studied") {
+            self.studied = studied.parse::<DailyCounts>().map_err(|e| {
+                VocaError::InvalidArgument(format!("unable to parse studied: {}", e))
+            })?;
+        }
+        if let Some(cloze) = args.value_of("cloze") {
+            match self
+                .columns
+                .iter()
+                .position(|column| column == cloze.trim())
+            {
+                Some(column) => {
+                    if self.clozecolumn != Some(column as u8) && !args.is_present("showcolumns") {
+                        //the layout of the sides depends on the cloze column, recompute the default
+                        self.showcolumns.clear();
+                    }
+                    self.clozecolumn = Some(column as u8)
+                }
+                None => return Err(VocaError::UnknownColumn(cloze.trim().to_owned())),
+            }
+        }
+        if let Some(scheduler) = args.value_of("scheduler") {
+            self.schedulertype = match scheduler {
+                "sm2" => SchedulerType::Sm2,
+                "fsrs" => SchedulerType::Fsrs,
+                _ => SchedulerType::Leitner,
+            };
+        }
+        if let Some(retention) = args.value_of("retention") {
+            match retention.trim().parse::<f64>() {
+                Ok(retention) if retention > 0.0 && retention < 1.0 => {
+                    self.retention = Some(retention)
+                }
+                _ => {
+                    return Err(VocaError::InvalidArgument(
+                        "retention must be a number between 0 and 1".to_owned(),
+                    ))
+                }
+            }
+        }
+        if let Some(weights) = args.value_of("weights") {
+            self.weights = weights
+                .trim()
+                .split(',')
+                .map(|s| s.trim().parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| {
+                    VocaError::InvalidArgument(format!("unable to parse weights: {}", e))
+                })?;
+            if self.weights.len() != FSRS_DEFAULT_WEIGHTS.len() {
+                return Err(VocaError::InvalidArgument(format!(
+                    "expected {} weights, got {}",
+                    FSRS_DEFAULT_WEIGHTS.len(),
+                    self.weights.len()
+                )));
+            }
+        }
 
         //sanity checks and defaults
-        if self.decks.len() > 0 && self.intervals.is_empty() {
+        if !self.decks.is_empty() && self.intervals.is_empty() {
         } else if self.decks.len() != self.intervals.len() {
-            return Err(Error::new(
-                ErrorKind::InvalidData,
-                "ERROR: intervals and decks have different length",
-            ));
+            return Err(VocaError::DeckIntervalMismatch {
+                decks: self.decks.len(),
+                intervals: self.intervals.len(),
+            });
         }
 
         if self.showcolumns.is_empty() {
-            //default configuration: two sides
-            self.showcolumns.push(vec![0]); //first column on front side
-            self.showcolumns
-                .push((1..self.columns.len()).map(|n| n as u8).collect()); //other columns on back side
+            let tagcolumn = self.tagcolumn();
+            if let Some(clozecolumn) = self.clozecolumn {
+                //default configuration for cloze deletions: the blanked column on the front side,
+                //the revealed column and all other columns (except tags) on the back side
+                self.showcolumns.push(vec![clozecolumn]);
+                self.showcolumns.push(
+                    std::iter::once(clozecolumn)
+                        .chain(
+                            (0..self.columns.len() as u8)
+                                .filter(|n| *n != clozecolumn && Some(*n) != tagcolumn),
+                        )
+                        .collect(),
+                );
+            } else {
+                //default configuration: two sides
+                self.showcolumns.push(vec![0]); //first column on front side
+                self.showcolumns.push(
+                    (1..self.columns.len() as u8)
+                        .filter(|n| Some(*n) != tagcolumn)
+                        .collect(),
+                ); //other columns (except tags) on back side
+            }
+        }
+        if let Some(directions) = args.values_of("direction") {
+            self.directions.clear();
+            for direction in directions {
+                self.directions.push(
+                    direction
+                        .trim()
+                        .split(',')
+                        .map(|s| match s.trim().parse::<u8>() {
+                            Ok(side) if side >= 1 => Ok(side - 1),
+                            _ => Err(VocaError::InvalidArgument(format!(
+                                "direction references a non-existing side: {}",
+                                s
+                            ))),
+                        })
+                        .collect::<Result<Vec<u8>, VocaError>>()?,
+                );
+            }
+        }
+        //directions may have been set before the sides (e.g. in the metadata, with --showcolumns on the command line)
+        if let Some(side) = self
+            .directions
+            .iter()
+            .flatten()
+            .find(|side| **side as usize >= self.showcolumns.len())
+        {
+            return Err(VocaError::InvalidArgument(format!(
+                "direction references a non-existing side: {} (there are {} sides)",
+                side + 1,
+                self.showcolumns.len()
+            )));
         }
         Ok(())
     }
 
-    pub fn from_arguments(args: Vec<&str>) -> Result<Self, Error> {
+    pub fn from_arguments(args: Vec<&str>) -> Result<Self, VocaError> {
         let mut vocasession = Self::default();
         let mut args = args.clone();
         args.insert(0, "metadata");
         let args = App::new("metadata")
             .args(&Self::common_arguments())
-            .get_matches_from(args);
+            .get_matches_from_safe(args)
+            .map_err(|e| {
+                VocaError::InvalidArgument(format!(
+                    "invalid metadata ({:?}): {}",
+                    e.kind,
+                    e.info.unwrap_or_default().join(", ")
+                ))
+            })?;
         vocasession.set_common_arguments(&args)?;
         Ok(vocasession)
     }
 
+    ///Returns the scheduler to use for this session
+    pub fn scheduler(&self) -> Box<dyn Scheduler> {
+        match self.schedulertype {
+            SchedulerType::Leitner => Box::new(Leitner),
+            SchedulerType::Sm2 => Box::new(Sm2),
+            SchedulerType::Fsrs => Box::new(self.fsrs()),
+        }
+    }
+
+    ///Returns an FSRS scheduler configured according to this session
+    pub fn fsrs(&self) -> Fsrs {
+        Fsrs {
+            retention: self.retention.unwrap_or(0.9),
+            weights: if self.weights.is_empty() {
+                FSRS_DEFAULT_WEIGHTS.to_vec()
+            } else {
+                self.weights.clone()
+            },
+        }
+    }
+
+    ///Returns the index of the column holding tags: the column named ``tags`` (case-insensitive)
+    pub fn tagcolumn(&self) -> Option<u8> {
+        self.columns
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case("tags"))
+            .map(|column| column as u8)
+    }
+
+    ///Returns the number of new cards and reviews studied on the day of the specified time
+    pub fn studied_on(&self, now: &NaiveDateTime) -> DailyCounts {
+        let today = local_date(now);
+        if self.studied.date == Some(today) {
+            self.studied
+        } else {
+            DailyCounts {
+                date: Some(today),
+                ..Default::default()
+            }
+        }
+    }
+
+    ///Counts a card that was studied at the specified time, either a new card or a review
+    pub fn count_studied(&mut self, new: bool, now: &NaiveDateTime) {
+        self.studied = self.studied_on(now);
+        if new {
+            self.studied.new += 1;
+        } else {
+            self.studied.reviews += 1;
+        }
+    }
+
+    ///Returns whether the card may still be presented today given the daily limits on new cards and reviews
+    pub fn within_daily_limits(&self, card: &VocaCard, now: &NaiveDateTime) -> bool {
+        let studied = self.studied_on(now);
+        if card.due.is_none() {
+            self.newperday.is_none_or(|limit| studied.new < limit)
+        } else {
+            self.reviewsperday
+                .is_none_or(|limit| studied.reviews < limit)
+        }
+    }
+
+    ///Returns whether the daily limit on new cards or on reviews has been reached
+    pub fn daily_limit_reached(&self, now: &NaiveDateTime) -> bool {
+        let studied = self.studied_on(now);
+        self.newperday.is_some_and(|limit| studied.new >= limit)
+            || self
+                .reviewsperday
+                .is_some_and(|limit| studied.reviews >= limit)
+    }
+
+    ///Returns the delimiter between tags
+    pub fn tagdelimiter(&self) -> &str {
+        self.tagdelimiter.as_deref().unwrap_or(" ")
+    }
+
+    ///Returns the file this session was loaded from
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
     pub fn get_deck_by_name(&self, name: &str) -> Option<u8> {
         for (i, n) in self.decks.iter().enumerate() {
             if n == name {
@@ -184,69 +899,267 @@ impl VocaSession {
     }
 }
 
-impl Default for VocaSession {
+impl Leitner {
+    ///Computes the due date for a card that is placed on the specified deck now
+    pub fn due(deck: u8, now: &NaiveDateTime, session: &VocaSession) -> Option<NaiveDateTime> {
+        session
+            .intervals
+            .get(deck as usize)
+            .map(|interval| *now + Duration::minutes(*interval as i64))
+    }
+}
+
+impl Scheduler for Leitner {
+    fn schedule(
+        &self,
+        card: &VocaCard,
+        grade: Grade,
+        now: &NaiveDateTime,
+        session: &VocaSession,
+    ) -> Schedule {
+        let lastdeck = (session.decks.len() as u8).saturating_sub(1);
+        let up = |n: u8| card.deck.saturating_add(n).min(lastdeck).max(card.deck);
+        let deck = match grade {
+            Grade::Again if session.returntofirst => 0,
+            Grade::Again => card.deck.saturating_sub(1),
+            Grade::Hard => card.deck,
+            Grade::Good => up(1),
+            Grade::Easy => up(2),
+        };
+        Schedule {
+            deck,
+            due: Leitner::due(deck, now, session).or(card.due),
+            sm2: card.sm2,
+            fsrs: card.fsrs,
+        }
+    }
+}
+
+impl Default for Sm2State {
     fn default() -> Self {
-        VocaSession {
-            columns: Vec::new(),
-            decks: Vec::new(),
-            intervals: Vec::new(),
-            returntofirst: false,
-            filename: None,
-            showcolumns: Vec::new(),
-            listdelimiter: None,
-            header: false,
+        Sm2State {
+            ease: 2.5,
+            interval: 0,
+            repetitions: 0,
+        }
+    }
+}
+
+impl Scheduler for Sm2 {
+    fn schedule(
+        &self,
+        card: &VocaCard,
+        grade: Grade,
+        now: &NaiveDateTime,
+        session: &VocaSession,
+    ) -> Schedule {
+        let mut schedule = Leitner.schedule(card, grade, now, session);
+        let mut state = card.sm2.unwrap_or_default();
+        let quality: f64 = match grade {
+            Grade::Again => 1.0,
+            Grade::Hard => 3.0,
+            Grade::Good => 4.0,
+            Grade::Easy => 5.0,
+        };
+        if grade == Grade::Again {
+            //start over, the card is due again right away
+            state.repetitions = 0;
+            state.interval = 0;
+        } else {
+            state.interval = match state.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (state.interval as f64 * state.ease).round() as u32,
+            };
+            state.repetitions += 1;
+        }
+        state.ease =
+            (state.ease + 0.1 - (5.0 - quality) * (0.08 + (5.0 - quality) * 0.02)).max(1.3);
+        schedule.due = Some(*now + Duration::days(state.interval as i64));
+        schedule.sm2 = Some(state);
+        schedule
+    }
+}
+
+impl Fsrs {
+    ///Initial difficulty for a card that is reviewed for the first time with the specified grade
+    fn initial_difficulty(&self, grade: f64) -> f64 {
+        (self.weights[4] - (grade - 3.0) * self.weights[5]).clamp(1.0, 10.0)
+    }
+
+    ///Probability of recall after the specified number of days
+    pub fn retrievability(elapsed: f64, stability: f64) -> f64 {
+        if stability <= 0.0 {
+            return 0.0;
+        }
+        (1.0 + FSRS_FACTOR * elapsed / stability).powf(FSRS_DECAY)
+    }
+
+    ///Interval in whole days after which the probability of recall drops to the target retention rate
+    pub fn interval(&self, stability: f64) -> i64 {
+        (stability / FSRS_FACTOR * (self.retention.powf(1.0 / FSRS_DECAY) - 1.0))
+            .round()
+            .max(1.0) as i64
+    }
+
+    ///Computes the new state of a card given the grade and the number of days elapsed since the last review
+    pub fn next_state(&self, state: Option<FsrsState>, grade: Grade, elapsed: f64) -> FsrsState {
+        let w = &self.weights;
+        let g: f64 = match grade {
+            Grade::Again => 1.0,
+            Grade::Hard => 2.0,
+            Grade::Good => 3.0,
+            Grade::Easy => 4.0,
+        };
+        let state = match state {
+            Some(state) if state.stability > 0.0 => state,
+            _ => {
+                //first review (or an incomplete state without stability)
+                return FsrsState {
+                    stability: w[g as usize - 1],
+                    difficulty: self.initial_difficulty(g),
+                    last: None,
+                };
+            }
+        };
+        let (s, d) = (state.stability, state.difficulty);
+        let r = Self::retrievability(elapsed.max(0.0), s);
+        let stability = if grade == Grade::Again {
+            (w[11] * d.powf(-w[12]) * ((s + 1.0).powf(w[13]) - 1.0) * (w[14] * (1.0 - r)).exp())
+                .min(s)
+        } else {
+            let hardpenalty = if grade == Grade::Hard { w[15] } else { 1.0 };
+            let easybonus = if grade == Grade::Easy { w[16] } else { 1.0 };
+            s * (1.0
+                + w[8].exp()
+                    * (11.0 - d)
+                    * s.powf(-w[9])
+                    * ((w[10] * (1.0 - r)).exp() - 1.0)
+                    * hardpenalty
+                    * easybonus)
+        };
+        let difficulty = d - w[6] * (g - 3.0);
+        //mean reversion towards the initial difficulty of a 'good' answer
+        let difficulty =
+            (w[7] * self.initial_difficulty(3.0) + (1.0 - w[7]) * difficulty).clamp(1.0, 10.0);
+        FsrsState {
+            stability: stability.max(0.01),
+            difficulty,
+            last: None,
+        }
+    }
+}
+
+impl Scheduler for Fsrs {
+    fn schedule(
+        &self,
+        card: &VocaCard,
+        grade: Grade,
+        now: &NaiveDateTime,
+        session: &VocaSession,
+    ) -> Schedule {
+        let mut schedule = Leitner.schedule(card, grade, now, session);
+        let lastreview = match (card.fsrs, card.due) {
+            (
+                Some(FsrsState {
+                    last: Some(last), ..
+                }),
+                _,
+            ) => Some(last),
+            //files written without the time of the last review: estimate it from the due date and the stability
+            (Some(state), Some(due)) => Some(due - Duration::days(self.interval(state.stability))),
+            _ => None,
+        };
+        let elapsed = match lastreview {
+            Some(lastreview) => (*now - lastreview).num_minutes() as f64 / 1440.0,
+            None => 0.0,
+        };
+        let mut state = self.next_state(card.fsrs, grade, elapsed);
+        state.last = Some(*now);
+        schedule.due = if grade == Grade::Again {
+            //relearn right away
+            Some(*now)
+        } else {
+            Some(*now + Duration::days(self.interval(state.stability)))
+        };
+        schedule.fsrs = Some(state);
+        schedule
+    }
+}
+
+impl fmt::Display for Grade {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Grade::Again => write!(f, "again"),
+            Grade::Hard => write!(f, "hard"),
+            Grade::Good => write!(f, "good"),
+            Grade::Easy => write!(f, "easy"),
+        }
+    }
+}
+
+impl fmt::Display for SchedulerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchedulerType::Leitner => write!(f, "leitner"),
+            SchedulerType::Sm2 => write!(f, "sm2"),
+            SchedulerType::Fsrs => write!(f, "fsrs"),
         }
     }
 }
 
 impl VocaData {
-    pub fn from_file(filename: &str, reset: bool) -> Result<Self, std::io::Error> {
+    ///Loads a dataset from file, configured by the metadata in the file. Lines are not yet split
+    ///into their variants (clozes or directions), call ``expand()`` once the session is fully configured.
+    pub fn from_file(filename: &str, reset: bool) -> Result<Self, VocaError> {
         let file = File::open(filename)?;
         let reader = BufReader::new(file);
-        let mut cards: Vec<VocaCard> = Vec::new();
+        //data lines (with their line numbers) are only parsed once all metadata is read,
+        //as the number of columns determines where the special columns start
+        let mut lines: Vec<(usize, String)> = Vec::new();
         let mut comments: Vec<(usize, String)> = Vec::new();
         let mut header: bool = false;
-        let mut columncount: u8 = 0;
         let mut metadata_args: Vec<String> = vec![];
         for (i, line) in reader.lines().enumerate() {
             let line = line?;
             if line.starts_with('#') {
                 //metadata or comment
-                if line.starts_with("#--") {
+                if let Some(metadata) = line.strip_prefix("#--") {
                     //metadata
-                    if let Some((index, _)) = line.chars().enumerate().find(|&r| r.1 == ' ') {
-                        metadata_args.push(format!("--{}", &line[3..index]).to_owned());
-                        metadata_args.push(line[index + 1..].to_owned());
+                    if let Some((key, value)) = metadata.split_once(' ') {
+                        metadata_args.push(format!("--{}", key));
+                        metadata_args.push(value.to_owned());
                     } else {
-                        metadata_args.push(format!("--{}", &line[3..]).to_owned());
+                        metadata_args.push(format!("--{}", metadata));
                     }
                 } else {
-                    comments.push((cards.len(), line)); //we store the index so we can later serialise it in proper order again
+                    comments.push((lines.len(), line)); //we store the index so we can later serialise it in proper order again
                 }
             } else if !line.is_empty() {
-                let card = VocaCard::parse_line(&line, reset, i + 1)?;
                 if i == 0
                     && !line.contains("deck#")
                     && !line.contains("due@")
                     && line == line.to_uppercase()
                 {
+                    let (card, _) = VocaCard::parse_line_lenient(&line, None, reset, i + 1);
                     metadata_args.push("--columns".to_owned());
                     metadata_args.push(card.fields.join(","));
                     header = true
                 } else {
-                    let length = card.fields.len() as u8;
-                    if length > columncount {
-                        columncount = length;
-                    }
-                    cards.push(card);
+                    lines.push((i + 1, line));
                 }
             } else {
                 //empty lines are considered comments for our purposes, we retain them in the output
-                comments.push((cards.len(), line));
+                comments.push((lines.len(), line));
             }
         }
         if !metadata_args.contains(&"--columns".to_owned()) {
             //no column/header information provided, infer
+            let columncount = lines
+                .iter()
+                .map(|(_, line)| data_column_count(&line.split('\t').collect::<Vec<&str>>()))
+                .max()
+                .unwrap_or(0);
             metadata_args.push("--columns".to_owned());
             metadata_args.push(
                 (1..=columncount)
@@ -260,82 +1173,298 @@ impl VocaData {
         session.header = header;
         session.filename = Some(filename.to_owned());
 
-        Ok(VocaData {
-            cards: cards,
-            session: session,
-            comments: comments,
-        })
-    }
+        //invalid special columns are reported per line rather than failing the whole file
+        let mut cards: Vec<VocaCard> = Vec::with_capacity(lines.len());
+        let mut warnings: Vec<VocaError> = Vec::new();
+        for (linenr, line) in lines.iter() {
+            let (card, errors) =
+                VocaCard::parse_line_lenient(line, Some(session.columns.len()), reset, *linenr);
+            warnings.extend(errors.into_iter().map(|e| e.in_file(filename)));
+            cards.push(card);
+        }
 
-    pub fn random_index(
-        &self,
-        rng: &mut impl Rng,
-        decks: Option<&Vec<u8>>,
-        due_only: bool,
-        seen_only: bool,
-    ) -> Option<(usize, usize)> {
-        let mut indices: Vec<usize> = Vec::new();
+        //the number of decks is only known once the metadata is read
+        if !session.decks.is_empty() {
+            for (card, (linenr, _)) in cards.iter().zip(lines) {
+                let deck = std::iter::once(card.deck)
+                    .chain(card.variantstates.iter().map(|state| state.schedule.deck))
+                    .max()
+                    .unwrap_or(0);
+                if deck as usize >= session.decks.len() {
+                    return Err(VocaError::parse(
+                        linenr,
+                        format!(
+                            "deck#{} does not exist, there are only {} decks",
+                            deck + 1,
+                            session.decks.len()
+                        ),
+                    )
+                    .in_file(filename));
+                }
+            }
+        }
 
-        let now: NaiveDateTime = NaiveDateTime::from_timestamp(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Unable to get time")
-                .as_secs() as i64,
-            0,
-        );
+        let mut data = VocaData {
+            cards,
+            session,
+            comments,
+            warnings,
+        };
+        data.assign_ids();
+        Ok(data)
+    }
 
-        for (i, card) in self.cards.iter().enumerate() {
-            if card.is_presentable(Some(&now), decks, due_only, seen_only) {
-                indices.push(i);
+    ///Splits every line into its variants (one card per cloze or direction) according to the
+    ///session configuration, comments are realigned accordingly. Lines that were expanded before
+    ///are joined again first, so this can be called again whenever the configuration changes.
+    pub fn expand(&mut self) {
+        let cards = std::mem::take(&mut self.cards);
+        let mut lines: Vec<VocaCard> = Vec::with_capacity(cards.len());
+        //maps the index of each card to the index of its line
+        let mut lineindex: Vec<usize> = Vec::with_capacity(cards.len() + 1);
+        for card in cards {
+            match lines.last_mut() {
+                Some(line) if card.variant.is_some() => {
+                    line.variantstates.push(card.variant_state())
+                }
+                _ => lines.push(card.collapse()),
             }
+            lineindex.push(lines.len() - 1);
         }
-
-        if !indices.is_empty() {
-            return Some((indices[rng.gen_range(0, indices.len())], indices.len()));
+        lineindex.push(lines.len());
+        let mut offsets: Vec<usize> = Vec::with_capacity(lines.len() + 1);
+        for line in lines {
+            offsets.push(self.cards.len());
+            self.cards.extend(line.expand(&self.session));
         }
-        None
+        offsets.push(self.cards.len());
+        for comment in self.comments.iter_mut() {
+            comment.0 = offsets[lineindex[comment.0]];
+        }
+        self.assign_ids();
     }
 
-    pub fn next_index(
-        &self,
-        index: usize,
-        decks: Option<&Vec<u8>>,
-        due_only: bool,
-        seen_only: bool,
-        inclusive: bool,
-    ) -> Option<(usize, usize)> {
-        let now: NaiveDateTime = NaiveDateTime::from_timestamp(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .expect("Unable to get time")
-                .as_secs() as i64,
-            0,
-        );
+    ///Assigns an identifier to all cards that do not have one yet. Identifiers are derived from
+    ///the front side of the card and the name of the file, so they are reproducible, but are guaranteed to be unique within the dataset.
+    ///This is done when loading a dataset and when adding cards, so every card has an identifier before it is written.
+    pub fn assign_ids(&mut self) {
+        self.assign_ids_except(&mut HashSet::new());
+    }
 
-        let mut next: Option<usize> = None;
-        let mut count: usize = 0;
+    ///Assigns an identifier to all cards that do not have one yet or whose identifier is already taken,
+    ///either by an earlier card (e.g. a copied line) or by the specified identifiers (e.g. those of
+    ///other loaded datasets). All identifiers of this dataset are added to the taken ones.
+    pub fn assign_ids_except(&mut self, taken: &mut HashSet<String>) {
+        //the first card with a given identifier keeps it, later duplicates get a new one
+        let mut unassigned: Vec<usize> = Vec::new();
         for (i, card) in self.cards.iter().enumerate() {
-            if (!inclusive && i > index) || (inclusive && i >= index) {
-                if card.is_presentable(Some(&now), decks, due_only, seen_only) {
-                    if next.is_none() {
-                        next = Some(i);
-                    } else {
-                        count += 1;
-                    }
-                }
+            match card.id.as_ref() {
+                Some(id) if taken.insert(id.clone()) => {}
+                _ => unassigned.push(i),
             }
         }
-        next.map(|i| (i, count))
+        let filename: Option<String> = self.session.filename.as_ref().and_then(|filename| {
+            Path::new(filename)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+        for i in unassigned {
+            let card = &mut self.cards[i];
+            let front: Vec<Cow<str>> = card
+                .fields_to_str(0, &self.session, false)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(_, field)| field)
+                .collect();
+            let mut front = front.join("\t");
+            if let Some(filename) = filename.as_ref() {
+                //so the same word in different files does not get the same identifier
+                front = format!("{}\t{}", filename, front);
+            }
+            let mut salt: u32 = 0;
+            let mut id = hash_id(&front);
+            while taken.contains(&id) {
+                salt += 1;
+                front = format!("{}\t{}", front, salt);
+                id = hash_id(&front);
+            }
+            taken.insert(id.clone());
+            card.id = Some(id);
+        }
+    }
+
+    ///Returns the index of the card with the specified identifier
+    pub fn get_index_by_id(&self, id: &str) -> Option<usize> {
+        self.cards
+            .iter()
+            .position(|card| card.id.as_deref() == Some(id))
+    }
+
+    ///Returns the card with the specified identifier
+    pub fn get_card_by_id(&self, id: &str) -> Option<&VocaCard> {
+        self.get_index_by_id(id).map(|index| &self.cards[index])
+    }
+
+    ///Returns the card with the specified identifier (mutably)
+    pub fn get_card_by_id_mut(&mut self, id: &str) -> Option<&mut VocaCard> {
+        self.get_index_by_id(id)
+            .map(move |index| &mut self.cards[index])
+    }
+
+    ///Returns the range of indices of all cards derived from the same line as the specified card
+    pub fn line(&self, index: usize) -> std::ops::Range<usize> {
+        let mut begin = index;
+        while begin > 0 && self.cards[begin].variant.is_some() {
+            begin -= 1;
+        }
+        let mut end = index + 1;
+        while end < self.cards.len() && self.cards[end].variant.is_some() {
+            end += 1;
+        }
+        begin..end
+    }
+
+    ///Returns the indices of all siblings of the specified card: the other variants (clozes or
+    ///directions) derived from the same line
+    pub fn siblings(&self, index: usize) -> Vec<usize> {
+        self.line(index).filter(|i| *i != index).collect()
+    }
+
+    ///Appends a new unseen card with the specified fields (along with its variants, e.g. one per
+    ///cloze), returns the index of the new card
+    pub fn add_card(&mut self, fields: Vec<String>) -> usize {
+        let index = self.cards.len();
+        let cards = VocaCard::new(fields).expand(&self.session);
+        self.cards.extend(cards);
+        self.assign_ids();
+        index
+    }
+
+    ///Serialises the line the specified card derives from: the card of the line itself, followed
+    ///by the state of all its other variants
+    pub fn line_to_string(&self, index: usize, reset: bool) -> String {
+        let line = self.line(index);
+        let mut result = self.cards[line.start].write_to_string(self.session.columns.len(), reset);
+        for variant in self.cards[line.start + 1..line.end].iter() {
+            result += variant.variant_state().write_to_string(reset).as_str();
+        }
+        result
+    }
+
+    ///Replaces the fields of the specified card, and of its siblings, which share the same line.
+    ///The line is split into its variants again, so clozes that were added or removed gain or lose
+    ///their card. Returns the new index of the card, or of the first card of the line if its variant
+    ///no longer exists.
+    pub fn update_fields(&mut self, index: usize, fields: Vec<String>) -> usize {
+        let line = self.line(index);
+        let label = self.cards[index].variant.clone();
+        let mut cards = self.cards.drain(line.clone());
+        let mut card = match cards.next() {
+            Some(card) => card.collapse(),
+            None => return index,
+        };
+        card.variantstates
+            .extend(cards.map(|variant| variant.variant_state()));
+        card.fields = fields;
+        self.insert_line(line, label, card)
+    }
+
+    ///Replaces the line the specified card derives from by a card representing the whole line, including the
+    ///state of its variants (e.g. a line that was edited as text, see ``line_to_string()``). Like ``update_fields()``,
+    ///returns the new index of the card, or of the first card of the line if its variant no longer exists.
+    pub fn replace_line(&mut self, index: usize, card: VocaCard) -> usize {
+        let line = self.line(index);
+        let label = self.cards[index].variant.clone();
+        self.cards.drain(line.clone());
+        self.insert_line(line, label, card)
+    }
+
+    ///Splits a line into its variants and inserts them where the (already removed) cards of the specified
+    ///range were, returns the new index of the variant with the specified label
+    fn insert_line(
+        &mut self,
+        line: std::ops::Range<usize>,
+        label: Option<String>,
+        card: VocaCard,
+    ) -> usize {
+        let expanded = card.expand(&self.session);
+        let offset = expanded
+            .iter()
+            .position(|card| card.variant == label)
+            .unwrap_or(0);
+        let count = expanded.len();
+        self.cards.splice(line.start..line.start, expanded);
+        for comment in self.comments.iter_mut() {
+            if comment.0 >= line.end {
+                comment.0 = comment.0 + count - line.len();
+            }
+        }
+        self.assign_ids();
+        line.start + offset
+    }
+
+    ///Returns whether the card with the specified index may be picked under the specified options
+    fn is_pickable(&self, index: usize, now: &NaiveDateTime, options: &PickOptions) -> bool {
+        let card = &self.cards[index];
+        card.is_presentable(now, options.decks, options.due_only, options.seen_only)
+            && self.session.within_daily_limits(card, now)
+            && options
+                .exclude
+                .is_none_or(|exclude| !exclude.contains(&index))
+            && options
+                .filter
+                .is_none_or(|filter| filter.matches(card, &self.session, now))
+    }
+
+    ///Returns the index of a random presentable card (and the number of presentable cards)
+    pub fn random_index(
+        &self,
+        rng: &mut impl Rng,
+        now: &NaiveDateTime,
+        options: &PickOptions,
+    ) -> Option<(usize, usize)> {
+        let indices: Vec<usize> = (0..self.cards.len())
+            .filter(|i| self.is_pickable(*i, now, options))
+            .collect();
+
+        if !indices.is_empty() {
+            return Some((indices[rng.gen_range(0, indices.len())], indices.len()));
+        }
+        None
+    }
+
+    ///Returns the index of the next presentable card after the specified index (and the number of
+    ///presentable cards remaining after it)
+    pub fn next_index(
+        &self,
+        index: usize,
+        now: &NaiveDateTime,
+        inclusive: bool,
+        options: &PickOptions,
+    ) -> Option<(usize, usize)> {
+        let mut next: Option<usize> = None;
+        let mut count: usize = 0;
+        for i in 0..self.cards.len() {
+            if ((!inclusive && i > index) || (inclusive && i >= index))
+                && self.is_pickable(i, now, options)
+            {
+                if next.is_none() {
+                    next = Some(i);
+                } else {
+                    count += 1;
+                }
+            }
+        }
+        next.map(|i| (i, count))
     }
 
     pub fn pick_card<'a>(
         &'a self,
         rng: &mut impl Rng,
-        decks: Option<&Vec<u8>>,
-        due_only: bool,
-        seen_only: bool,
+        now: &NaiveDateTime,
+        options: &PickOptions,
     ) -> Option<&'a VocaCard> {
-        if let Some((choice, _)) = self.random_index(rng, decks, due_only, seen_only) {
+        if let Some((choice, _)) = self.random_index(rng, now, options) {
             return Some(&self.cards[choice]);
         }
         None
@@ -344,11 +1473,10 @@ impl VocaData {
     pub fn pick_card_mut<'a>(
         &'a mut self,
         rng: &mut impl Rng,
-        decks: Option<&Vec<u8>>,
-        due_only: bool,
-        seen_only: bool,
+        now: &NaiveDateTime,
+        options: &PickOptions,
     ) -> Option<&'a mut VocaCard> {
-        if let Some((choice, _)) = self.random_index(rng, decks, due_only, seen_only) {
+        if let Some((choice, _)) = self.random_index(rng, now, options) {
             return Some(&mut self.cards[choice]);
         }
         None
@@ -357,12 +1485,11 @@ impl VocaData {
     pub fn pick_next_card<'a>(
         &'a self,
         index: usize,
-        decks: Option<&Vec<u8>>,
-        due_only: bool,
-        seen_only: bool,
+        now: &NaiveDateTime,
         inclusive: bool,
+        options: &PickOptions,
     ) -> Option<&'a VocaCard> {
-        if let Some((choice, _)) = self.next_index(index, decks, due_only, seen_only, inclusive) {
+        if let Some((choice, _)) = self.next_index(index, now, inclusive, options) {
             return Some(&self.cards[choice]);
         }
         None
@@ -371,29 +1498,32 @@ impl VocaData {
     pub fn pick_next_card_mut<'a>(
         &'a mut self,
         index: usize,
-        decks: Option<&Vec<u8>>,
-        due_only: bool,
-        seen_only: bool,
+        now: &NaiveDateTime,
         inclusive: bool,
+        options: &PickOptions,
     ) -> Option<&'a mut VocaCard> {
-        if let Some((choice, _)) = self.next_index(index, decks, due_only, seen_only, inclusive) {
+        if let Some((choice, _)) = self.next_index(index, now, inclusive, options) {
             return Some(&mut self.cards[choice]);
         }
         None
     }
 
+    ///Writes the dataset back to file
     pub fn write(&self, reset: bool) -> Result<(), std::io::Error> {
-        if self.session.filename.is_none() {
-            return Err(std::io::Error::new(
-                ErrorKind::InvalidData,
-                "No filename configured",
-            ));
-        }
-        let mut file = std::fs::File::create(self.session.filename.as_ref().unwrap().as_str())?;
+        let filename = match self.session.filename.as_ref() {
+            Some(filename) => filename,
+            None => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "No filename configured",
+                ))
+            }
+        };
+        let mut file = std::fs::File::create(filename.as_str())?;
         //contents
         if self.session.header {
-            file.write(self.session.columns.join("\t").as_bytes())?;
-            file.write(b"\n")?;
+            file.write_all(self.session.columns.join("\t").as_bytes())?;
+            file.write_all(b"\n")?;
         }
         let mut nextcommentindex = if !self.comments.is_empty() {
             //initialise
@@ -402,12 +1532,16 @@ impl VocaData {
             None
         };
         for (i, card) in self.cards.iter().enumerate() {
+            if card.variant.is_some() {
+                //variants are written along with the line they derive from
+                continue;
+            }
             //make sure to process very first comments
             if i == 0 && nextcommentindex.is_some() && nextcommentindex.unwrap() == 0 {
                 for (commentindex, comment) in self.comments.iter() {
                     if *commentindex == 0 {
-                        file.write(comment.as_bytes())?;
-                        file.write(b"\n")?;
+                        file.write_all(comment.as_bytes())?;
+                        file.write_all(b"\n")?;
                         nextcommentindex = None; //reset
                     } else if *commentindex > 0 {
                         nextcommentindex = Some(*commentindex); //set for next
@@ -415,19 +1549,18 @@ impl VocaData {
                     }
                 }
             }
-            file.write(
-                card.write_to_string(self.session.columns.len(), reset)
-                    .as_bytes(),
-            )?;
-            file.write(b"\n")?;
+            let line = self.line_to_string(i, reset);
+            let next = self.line(i).end;
+            file.write_all(line.as_bytes())?;
+            file.write_all(b"\n")?;
             //process remaining comments
-            if nextcommentindex.is_some() && i + 1 == nextcommentindex.unwrap() {
+            if nextcommentindex.is_some() && next == nextcommentindex.unwrap() {
                 for (commentindex, comment) in self.comments.iter() {
-                    if *commentindex == i + 1 {
-                        file.write(comment.as_bytes())?;
-                        file.write(b"\n")?;
+                    if *commentindex == next {
+                        file.write_all(comment.as_bytes())?;
+                        file.write_all(b"\n")?;
                         nextcommentindex = None; //reset
-                    } else if *commentindex > i + 1 {
+                    } else if *commentindex > next {
                         nextcommentindex = Some(*commentindex); //set for next
                         break;
                     }
@@ -436,13 +1569,13 @@ impl VocaData {
         }
         //metadata last
         if !self.session.decks.is_empty() {
-            file.write(b"#--decks ")?;
-            file.write(self.session.decks.join(",").as_bytes())?;
-            file.write(b"\n")?;
+            file.write_all(b"#--decks ")?;
+            file.write_all(self.session.decks.join(",").as_bytes())?;
+            file.write_all(b"\n")?;
         }
         if !self.session.intervals.is_empty() {
-            file.write(b"#--intervals ")?;
-            file.write(
+            file.write_all(b"#--intervals ")?;
+            file.write_all(
                 self.session
                     .intervals
                     .iter()
@@ -451,33 +1584,89 @@ impl VocaData {
                     .join(",")
                     .as_bytes(),
             )?;
-            file.write(b"\n")?;
+            file.write_all(b"\n")?;
         }
         if let Some(listdelimiter) = &self.session.listdelimiter {
-            file.write(b"#--listdelimiter ")?;
-            file.write(listdelimiter.as_bytes())?;
-            file.write(b"\n")?;
+            file.write_all(b"#--listdelimiter ")?;
+            file.write_all(listdelimiter.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        if let Some(tagdelimiter) = &self.session.tagdelimiter {
+            file.write_all(b"#--tagdelimiter ")?;
+            file.write_all(tagdelimiter.as_bytes())?;
+            file.write_all(b"\n")?;
         }
         if self.session.returntofirst {
-            file.write(b"#--returntofirst\n")?;
+            file.write_all(b"#--returntofirst\n")?;
+        }
+        if let Some(leechthreshold) = self.session.leechthreshold {
+            file.write_all(format!("#--leechthreshold {}\n", leechthreshold).as_bytes())?;
+        }
+        if self.session.leechsuspend {
+            file.write_all(b"#--leechsuspend\n")?;
+        }
+        if let Some(newperday) = self.session.newperday {
+            file.write_all(format!("#--new-per-day {}\n", newperday).as_bytes())?;
+        }
+        if let Some(reviewsperday) = self.session.reviewsperday {
+            file.write_all(format!("#--reviews-per-day {}\n", reviewsperday).as_bytes())?;
+        }
+        if self.session.studied.date.is_some() {
+            file.write_all(format!("#--studied {}\n", self.session.studied).as_bytes())?;
+        }
+        if self.session.schedulertype != SchedulerType::Leitner {
+            file.write_all(format!("#--scheduler {}\n", self.session.schedulertype).as_bytes())?;
+        }
+        if let Some(retention) = self.session.retention {
+            file.write_all(format!("#--retention {}\n", retention).as_bytes())?;
+        }
+        if !self.session.weights.is_empty() {
+            file.write_all(b"#--weights ")?;
+            file.write_all(
+                self.session
+                    .weights
+                    .iter()
+                    .map(|w| format!("{}", w))
+                    .collect::<Vec<String>>()
+                    .join(",")
+                    .as_bytes(),
+            )?;
+            file.write_all(b"\n")?;
         }
         if !self.session.columns.is_empty() {
             if !self.session.header {
-                file.write(b"#--columns ")?;
-                file.write(self.session.columns.join(",").as_bytes())?;
-                file.write(b"\n")?;
+                file.write_all(b"#--columns ")?;
+                file.write_all(self.session.columns.join(",").as_bytes())?;
+                file.write_all(b"\n")?;
+            }
+            for direction in self.session.directions.iter() {
+                file.write_all(b"#--direction ")?;
+                file.write_all(
+                    direction
+                        .iter()
+                        .map(|side| format!("{}", side + 1))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                        .as_bytes(),
+                )?;
+                file.write_all(b"\n")?;
+            }
+            if let Some(column) = self.session.clozecolumn {
+                file.write_all(b"#--cloze ")?;
+                file.write_all(self.session.columns[column as usize].as_bytes())?;
+                file.write_all(b"\n")?;
             }
             for showcolumns in self.session.showcolumns.iter() {
-                file.write(b"#--showcolumns ")?;
-                file.write(
+                file.write_all(b"#--showcolumns ")?;
+                file.write_all(
                     showcolumns
                         .iter()
-                        .map(|n| format!("{}", self.session.columns[*n as usize]).to_string())
+                        .map(|n| self.session.columns[*n as usize].to_string())
                         .collect::<Vec<String>>()
                         .join(",")
                         .as_bytes(),
                 )?;
-                file.write(b"\n")?;
+                file.write_all(b"\n")?;
             }
         }
         Ok(())
@@ -485,51 +1674,210 @@ impl VocaData {
 }
 
 impl VocaCard {
-    pub fn parse_line(line: &str, reset: bool, linenr: usize) -> Result<VocaCard, std::io::Error> {
-        let mut begin = 0;
+    ///Creates a new unseen card with the specified fields
+    pub fn new(fields: Vec<String>) -> Self {
+        VocaCard {
+            fields,
+            due: None,
+            deck: 0,
+            sm2: None,
+            fsrs: None,
+            id: None,
+            state: CardState::default(),
+            lapses: 0,
+            cloze: None,
+            direction: 0,
+            variant: None,
+            variantstates: Vec::new(),
+        }
+    }
+
+    ///Parses a line into a card, see ``parse_line_lenient()``, fails on the first invalid special column
+    pub fn parse_line(
+        line: &str,
+        columncount: Option<usize>,
+        reset: bool,
+        linenr: usize,
+    ) -> Result<VocaCard, VocaError> {
+        let (card, mut errors) = VocaCard::parse_line_lenient(line, columncount, reset, linenr);
+        if errors.is_empty() {
+            Ok(card)
+        } else {
+            Err(errors.remove(0))
+        }
+    }
+
+    ///Parses a line into a card. The first ``columncount`` columns are always data fields, special columns
+    ///are only recognised after them (or after the last data field if the number of columns is not known).
+    ///Special columns with an invalid value are retained as they are, so they are written back unaltered, and
+    ///are returned as errors rather than failing the whole line.
+    pub fn parse_line_lenient(
+        line: &str,
+        columncount: Option<usize>,
+        reset: bool,
+        linenr: usize,
+    ) -> (VocaCard, Vec<VocaError>) {
+        let values: Vec<&str> = line.split('\t').collect();
+        let datacount = data_column_count(&values).max(columncount.unwrap_or(0));
         let mut fields: Vec<String> = Vec::new();
-        let mut deck: u8 = 0;
-        let mut due: Option<NaiveDateTime> = None;
-        let length = line.chars().count();
-        for (j, (i, c)) in line.char_indices().enumerate() {
-            if (j == length - 1) || (c == '\t') {
-                //handle previous column
-                let value = &line[begin..if j == length - 1 { line.len() } else { i }];
-                if value.starts_with("deck#") {
-                    if !reset {
-                        if let Ok(num) = &value[5..].parse::<u8>() {
-                            deck = *num - 1;
-                        }
-                    }
-                } else if value.starts_with("due@") {
-                    if !reset {
-                        due = match NaiveDateTime::parse_from_str(&value[4..], "%Y-%m-%d %H:%M:%S")
-                        {
-                            Ok(dt) => Some(dt),
-                            Err(e) => {
-                                return Err(std::io::Error::new(
-                                    ErrorKind::InvalidData,
-                                    format!("Unable to parse due date on line {}: {}", linenr, e),
-                                ));
-                            }
-                        };
-                    }
+        let mut primary = VariantState::default();
+        let mut variantstates: Vec<VariantState> = Vec::new();
+        let mut errors: Vec<VocaError> = Vec::new();
+        for (i, value) in values.into_iter().enumerate() {
+            if i < datacount {
+                if value.is_empty() || value == "-" {
+                    //empty field placeholder
+                    fields.push(String::new());
                 } else {
-                    if value.is_empty() || value == "-" {
-                        //empty field placeholder
-                        fields.push(String::new());
-                    } else {
-                        fields.push(value.trim().to_owned());
-                    }
+                    fields.push(value.trim().to_owned());
                 }
-                begin = i + 1
+                continue;
+            }
+            let result = if let Some((label, value)) = split_variant_label(value) {
+                //state of a variant of this card
+                let index = match variantstates.iter().position(|state| state.label == label) {
+                    Some(index) => index,
+                    None => {
+                        variantstates.push(VariantState {
+                            label: label.to_owned(),
+                            ..Default::default()
+                        });
+                        variantstates.len() - 1
+                    }
+                };
+                parse_state_column(value, &mut variantstates[index], reset, linenr)
+            } else {
+                parse_state_column(value, &mut primary, reset, linenr)
+            };
+            if let Err(error) = result {
+                fields.push(value.to_owned());
+                errors.push(error);
             }
         }
-        Ok(VocaCard {
-            fields: fields,
-            due: due,
-            deck: deck,
-        })
+        let card = VocaCard {
+            fields,
+            due: primary.schedule.due,
+            deck: primary.schedule.deck,
+            sm2: primary.schedule.sm2,
+            fsrs: primary.schedule.fsrs,
+            id: primary.id,
+            state: primary.state,
+            lapses: primary.lapses,
+            cloze: None,
+            direction: 0,
+            variant: None,
+            variantstates,
+        };
+        (card, errors)
+    }
+
+    ///Splits the card into its variants: one card per cloze if the session has a cloze column,
+    ///otherwise one card per direction if the session has multiple directions
+    pub fn expand(self, session: &VocaSession) -> Vec<VocaCard> {
+        let mut cards = match session.clozecolumn {
+            Some(column) => self.expand_clozes(column),
+            None => vec![self],
+        };
+        if cards[0].cloze.is_none() && session.directions.len() > 1 {
+            //directions do not apply to cloze deletion cards
+            cards = cards
+                .remove(0)
+                .expand_directions(session.directions.len() as u8);
+        }
+        cards
+    }
+
+    ///Splits the card into one card per cloze in the specified column, the state of all but the first
+    ///cloze is taken from the variant states (labelled ``c2``, ``c3``, etc). Cards without clozes are returned as is.
+    pub fn expand_clozes(mut self, column: u8) -> Vec<VocaCard> {
+        let mut numbers: Vec<u8> = self
+            .fields
+            .get(column as usize)
+            .map(|field| {
+                cloze_gaps(field)
+                    .into_iter()
+                    .map(|gap| gap.number)
+                    .collect()
+            })
+            .unwrap_or_default();
+        numbers.sort_unstable();
+        numbers.dedup();
+        if numbers.is_empty() {
+            return vec![self];
+        }
+        self.cloze = Some(Cloze {
+            column,
+            number: numbers[0],
+        });
+        let mut cards: Vec<VocaCard> = Vec::with_capacity(numbers.len());
+        for number in numbers.into_iter().skip(1) {
+            let mut card = self.derive_variant(format!("c{}", number));
+            card.cloze = Some(Cloze { column, number });
+            cards.push(card);
+        }
+        cards.insert(0, self);
+        cards
+    }
+
+    ///Splits the card into one card per direction, the state of all but the first direction is
+    ///taken from the variant states (labelled ``d2``, ``d3``, etc).
+    pub fn expand_directions(mut self, count: u8) -> Vec<VocaCard> {
+        let mut cards: Vec<VocaCard> = Vec::with_capacity(count as usize);
+        for direction in 1..count {
+            let mut card = self.derive_variant(format!("d{}", direction + 1));
+            card.direction = direction;
+            cards.push(card);
+        }
+        cards.insert(0, self);
+        cards
+    }
+
+    ///Turns a (primary) card back into a card representing the whole line, before it was split into variants
+    fn collapse(mut self) -> VocaCard {
+        self.cloze = None;
+        self.direction = 0;
+        self
+    }
+
+    ///Creates a virtual card with the same fields as this card, for the variant with the specified
+    ///label. Its state is taken from (and removed from) the variant states of this card.
+    fn derive_variant(&mut self, label: String) -> VocaCard {
+        let state = match self
+            .variantstates
+            .iter()
+            .position(|state| state.label == label)
+        {
+            Some(index) => self.variantstates.remove(index),
+            None => VariantState {
+                label,
+                ..Default::default()
+            },
+        };
+        VocaCard {
+            fields: self.fields.clone(),
+            due: state.schedule.due,
+            deck: state.schedule.deck,
+            sm2: state.schedule.sm2,
+            fsrs: state.schedule.fsrs,
+            id: state.id,
+            state: state.state,
+            lapses: state.lapses,
+            cloze: None,
+            direction: 0,
+            variant: Some(state.label),
+            variantstates: Vec::new(),
+        }
+    }
+
+    ///Returns the scheduling state of this card as a variant state, labelled with the variant label
+    pub fn variant_state(&self) -> VariantState {
+        VariantState {
+            label: self.variant.clone().unwrap_or_default(),
+            schedule: self.schedule(),
+            id: self.id.clone(),
+            state: self.state,
+            lapses: self.lapses,
+        }
     }
 
     pub fn write_to_string(&self, columncount: usize, reset: bool) -> String {
@@ -566,46 +1914,149 @@ impl VocaCard {
             } else {
                 result += "\t";
             }
+            if let Some(sm2) = self.sm2 {
+                result = format!(
+                    "{}\tease@{:.2}\tinterval@{}\treps@{}",
+                    result, sm2.ease, sm2.interval, sm2.repetitions
+                );
+            }
+            if let Some(fsrs) = self.fsrs {
+                result = format!(
+                    "{}\tstability@{:.4}\tdifficulty@{:.4}",
+                    result, fsrs.stability, fsrs.difficulty
+                );
+                if let Some(last) = fsrs.last {
+                    result = format!("{}\tlast@{}", result, last.format("%Y-%m-%d %H:%M:%S"));
+                }
+            }
+            if self.lapses > 0 {
+                result = format!("{}\tlapses@{}", result, self.lapses);
+            }
+        }
+        if self.state != CardState::default() {
+            result = format!("{}\tstate@{}", result, self.state);
+        }
+        if let Some(id) = &self.id {
+            result = format!("{}\tid@{}", result, id);
+        }
+        for state in self.variantstates.iter() {
+            result += state.write_to_string(reset).as_str();
         }
         result
     }
 
-    pub fn move_to_deck(&mut self, deck: u8, session: &VocaSession) -> bool {
+    ///Returns the scheduling state of this card
+    pub fn schedule(&self) -> Schedule {
+        Schedule {
+            deck: self.deck,
+            due: self.due,
+            sm2: self.sm2,
+            fsrs: self.fsrs,
+        }
+    }
+
+    ///Sets the scheduling state of this card
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.deck = schedule.deck;
+        self.due = schedule.due;
+        self.sm2 = schedule.sm2;
+        self.fsrs = schedule.fsrs;
+    }
+
+    ///Returns a string identifying this card: its identifier, or the contents of its front side
+    ///if it has none
+    pub fn identity(&self, session: &VocaSession) -> String {
+        if let Some(id) = &self.id {
+            return id.clone();
+        }
+        self.fields_to_str(0, session, false)
+            .map(|fields| {
+                fields
+                    .into_iter()
+                    .map(|(_, field)| field)
+                    .collect::<Vec<Cow<str>>>()
+                    .join(" ")
+            })
+            .unwrap_or_default()
+    }
+
+    ///Returns the current interval of the card in minutes, according to the session's scheduler
+    pub fn interval(&self, session: &VocaSession) -> Option<u32> {
+        match (session.schedulertype, self.sm2, self.fsrs) {
+            (SchedulerType::Sm2, Some(sm2), _) => Some(sm2.interval * 1440),
+            (SchedulerType::Fsrs, _, Some(fsrs)) => {
+                Some(session.fsrs().interval(fsrs.stability) as u32 * 1440)
+            }
+            _ => session.intervals.get(self.deck as usize).copied(),
+        }
+    }
+
+    ///Computes what the scheduling state of the card would be if it were reviewed at the specified time with the
+    ///specified grade, without actually changing the card
+    pub fn preview(
+        &self,
+        grade: Grade,
+        scheduler: &dyn Scheduler,
+        now: &NaiveDateTime,
+        session: &VocaSession,
+    ) -> Schedule {
+        scheduler.schedule(self, grade, now, session)
+    }
+
+    ///Reviews the card with the specified grade and reschedules it according to the scheduler.
+    ///If the card drops to a lower deck, this counts as a lapse. Returns the previous scheduling state.
+    pub fn review(
+        &mut self,
+        grade: Grade,
+        scheduler: &dyn Scheduler,
+        now: &NaiveDateTime,
+        session: &VocaSession,
+    ) -> Schedule {
+        let schedule = scheduler.schedule(self, grade, now, session);
+        let previous = self.schedule();
+        self.set_schedule(schedule);
+        if self.deck < previous.deck {
+            self.lapse(session);
+        }
+        previous
+    }
+
+    ///Registers a lapse, marks the card as a leech (and suspends it, if so configured) once the
+    ///number of lapses reaches the session's threshold
+    fn lapse(&mut self, session: &VocaSession) {
+        self.lapses += 1;
+        if !self.state.leech && session.leechthreshold.is_some_and(|t| self.lapses >= t) {
+            self.state.leech = true;
+            if session.leechsuspend {
+                self.state.suspended = true;
+            }
+        }
+    }
+
+    pub fn move_to_deck(&mut self, deck: u8, now: &NaiveDateTime, session: &VocaSession) -> bool {
         if deck >= session.decks.len() as u8 {
             return false;
         }
-        if let Some(interval) = session.intervals.get(deck as usize) {
-            self.due = Some(NaiveDateTime::from_timestamp(
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Unable to get time")
-                    .as_secs() as i64
-                    + (interval * 60) as i64,
-                0,
-            ));
+        if let Some(due) = Leitner::due(deck, now, session) {
+            self.due = Some(due);
+        }
+        if deck < self.deck {
+            self.lapse(session);
         }
         self.deck = deck;
         true
     }
 
-    pub fn promote(&mut self, session: &VocaSession) -> bool {
-        if ((self.deck + 1) as usize) < session.decks.len() {
-            self.move_to_deck(self.deck + 1, session);
-            true
-        } else {
-            self.move_to_deck(self.deck, session);
-            false
-        }
+    ///Promotes the card according to the session's scheduler, returns true if it moved to a higher deck
+    pub fn promote(&mut self, now: &NaiveDateTime, session: &VocaSession) -> bool {
+        let previous = self.review(Grade::Good, session.scheduler().as_ref(), now, session);
+        self.deck > previous.deck
     }
 
-    pub fn demote(&mut self, session: &VocaSession) -> bool {
-        if self.deck > 0 && !session.returntofirst {
-            self.move_to_deck(self.deck - 1, session);
-            true
-        } else {
-            self.move_to_deck(0, session);
-            false
-        }
+    ///Demotes the card according to the session's scheduler, returns true if it moved to a lower deck
+    pub fn demote(&mut self, now: &NaiveDateTime, session: &VocaSession) -> bool {
+        let previous = self.review(Grade::Again, session.scheduler().as_ref(), now, session);
+        self.deck < previous.deck
     }
 
     pub fn print(
@@ -620,11 +2071,11 @@ impl VocaCard {
             match format {
                 PrintFormat::Plain => println!("{}", output),
                 PrintFormat::AnsiColour => match index {
-                    0 => println!("{}", Colour::Green.paint(output).to_string()),
-                    1 => println!("{}", Colour::Cyan.paint(output).to_string()),
-                    2 => println!("{}", Colour::Yellow.paint(output).to_string()),
-                    3 => println!("{}", Colour::Purple.paint(output).to_string()),
-                    4 => println!("{}", Colour::Blue.paint(output).to_string()),
+                    0 => println!("{}", Colour::Green.paint(output)),
+                    1 => println!("{}", Colour::Cyan.paint(output)),
+                    2 => println!("{}", Colour::Yellow.paint(output)),
+                    3 => println!("{}", Colour::Purple.paint(output)),
+                    4 => println!("{}", Colour::Blue.paint(output)),
                     _ => println!("{}", output),
                 },
             }
@@ -637,11 +2088,11 @@ impl VocaCard {
         side: u8,
         session: &VocaSession,
         wraplist: bool,
-    ) -> Result<Vec<(u8, &str)>, std::fmt::Error> {
-        if let Some(showcolumns) = session.showcolumns.get(side as usize) {
-            let mut output: Vec<(u8, &str)> = Vec::new();
+    ) -> Result<Vec<(u8, Cow<'_, str>)>, std::fmt::Error> {
+        if let Some(showcolumns) = self.side_columns(side, session) {
+            let mut output: Vec<(u8, Cow<str>)> = Vec::new();
             for showcolumn in showcolumns.iter() {
-                let lines = self.field_to_str(*showcolumn, session, wraplist)?;
+                let lines = self.field_to_str(*showcolumn, side, session, wraplist)?;
                 for line in lines {
                     output.push((*showcolumn, line));
                 }
@@ -652,21 +2103,49 @@ impl VocaCard {
         }
     }
 
+    ///Returns the number of sides of this card
+    pub fn sides(&self, session: &VocaSession) -> u8 {
+        match session.directions.get(self.direction as usize) {
+            Some(direction) => direction.len() as u8,
+            None => session.showcolumns.len() as u8,
+        }
+    }
+
+    ///Maps a side of this card to the side of the template (``VocaSession::showcolumns``) it shows,
+    ///according to the direction of the card
+    pub fn template_side(&self, side: u8, session: &VocaSession) -> Option<u8> {
+        match session.directions.get(self.direction as usize) {
+            Some(direction) => direction.get(side as usize).copied(),
+            None => Some(side),
+        }
+    }
+
+    ///Returns the columns shown on the specified side of this card
+    pub fn side_columns<'a>(&self, side: u8, session: &'a VocaSession) -> Option<&'a Vec<u8>> {
+        session
+            .showcolumns
+            .get(self.template_side(side, session)? as usize)
+    }
+
+    ///Returns the field with the specified index as it is to be shown on the specified side of the card. For cloze
+    ///deletion cards, the gap is blanked on the front side and revealed on all other sides.
     pub fn field_to_str(
         &self,
         index: u8,
+        side: u8,
         session: &VocaSession,
         wraplist: bool,
-    ) -> Result<Vec<&str>, std::fmt::Error> {
+    ) -> Result<Vec<Cow<'_, str>>, std::fmt::Error> {
         if let Some(field) = self.fields.get(index as usize) {
-            let output: Vec<&str> = if let Some(listdelimiter) = &session.listdelimiter {
-                if wraplist {
-                    field.split(listdelimiter.as_str()).collect()
-                } else {
-                    vec![field.as_str()]
+            let output: Vec<Cow<str>> = match (self.cloze, &session.listdelimiter) {
+                (Some(cloze), _) if cloze.column == index => {
+                    vec![Cow::Owned(render_cloze(field, cloze.number, side > 0))]
                 }
-            } else {
-                vec![field.as_str()]
+                (_, Some(listdelimiter)) if wraplist => field
+                    .split(listdelimiter.as_str())
+                    .map(Cow::Borrowed)
+                    .collect(),
+                _ => vec![Cow::Borrowed(field.as_str())],
             };
             Ok(output)
         } else {
@@ -674,28 +2153,152 @@ impl VocaCard {
         }
     }
 
+    ///Returns the text of the gap(s) this card asks for, if it is a cloze deletion card
+    pub fn cloze_answer(&self) -> Option<String> {
+        let cloze = self.cloze?;
+        let field = self.fields.get(cloze.column as usize)?;
+        Some(
+            cloze_gaps(field)
+                .into_iter()
+                .filter(|gap| gap.number == cloze.number)
+                .map(|gap| gap.answer)
+                .collect::<Vec<&str>>()
+                .join(" "),
+        )
+    }
+
+    ///Returns the tags of the card, from the ``tags`` column
+    pub fn tags(&self, session: &VocaSession) -> Vec<&str> {
+        match session
+            .tagcolumn()
+            .and_then(|column| self.fields.get(column as usize))
+        {
+            Some(field) => field
+                .split(session.tagdelimiter())
+                .map(|tag| tag.trim())
+                .filter(|tag| !tag.is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    ///Returns whether the card has the specified tag
+    pub fn has_tag(&self, tag: &str, session: &VocaSession) -> bool {
+        self.tags(session).contains(&tag)
+    }
+
+    ///Adds the tag to the card if it does not have it yet, or removes it otherwise. Returns true if the tag was added,
+    ///or None if the session has no ``tags`` column.
+    pub fn toggle_tag(&mut self, tag: &str, session: &VocaSession) -> Option<bool> {
+        let column = session.tagcolumn()? as usize;
+        let mut tags: Vec<String> = self
+            .tags(session)
+            .into_iter()
+            .map(|tag| tag.to_owned())
+            .collect();
+        let added = if let Some(index) = tags.iter().position(|t| t == tag) {
+            tags.remove(index);
+            false
+        } else {
+            tags.push(tag.to_owned());
+            true
+        };
+        if self.fields.len() <= column {
+            self.fields.resize(column + 1, String::new());
+        }
+        self.fields[column] = tags.join(session.tagdelimiter());
+        Some(added)
+    }
+
+    ///Returns the expected answer for the specified column: the text of the gap for cloze deletion
+    ///cards, the field itself otherwise
+    pub fn answer(&self, column: u8) -> Option<String> {
+        self.cloze_answer()
+            .or_else(|| self.fields.get(column as usize).cloned())
+    }
+
+    ///Returns the column holding the answer for the specified side of the card: the first column shown on that side
+    pub fn answer_column(&self, side: u8, session: &VocaSession) -> Option<u8> {
+        self.side_columns(side, session)
+            .and_then(|columns| columns.first().copied())
+    }
+
+    ///Checks a typed answer against the answer column of the specified side of the card (see ``answer_column()``), or against the gap
+    ///for cloze deletion cards. Every list item (see ``VocaSession::listdelimiter``) is accepted as an alternative answer. Matching is
+    ///case-insensitive; answers that differ only in accents or by a small typo receive the grade 'hard'
+    ///(typos are not tolerated in answers shorter than six characters, where a single edit easily yields another word). An empty answer is always graded 'again'.
+    pub fn check_answer(
+        &self,
+        answer: &str,
+        side: u8,
+        session: &VocaSession,
+    ) -> Option<AnswerCheck> {
+        let answer = normalize_answer(answer, false);
+        let answer_folded = normalize_answer(&answer, true);
+        let mut best: Option<AnswerCheck> = None;
+        let expected: Vec<Cow<str>> = match self.cloze_answer() {
+            Some(answer) => vec![Cow::Owned(answer)],
+            None => self
+                .field_to_str(self.answer_column(side, session)?, side, session, true)
+                .ok()?,
+        };
+        for expected in expected {
+            let normalized = normalize_answer(&expected, false);
+            if normalized.is_empty() {
+                continue;
+            }
+            let check = if normalized == answer {
+                AnswerCheck {
+                    grade: Grade::Good,
+                    expected: expected.trim().to_owned(),
+                    distance: 0,
+                }
+            } else {
+                let distance = edit_distance(&answer, &normalized);
+                //no typos are tolerated in short answers, where a single edit changes the word
+                let length = normalized.chars().count();
+                let tolerance = if length < 6 { 0 } else { (length / 5).max(1) };
+                let grade = if answer.is_empty() {
+                    Grade::Again
+                } else if normalize_answer(&normalized, true) == answer_folded
+                    || distance <= tolerance
+                {
+                    Grade::Hard
+                } else {
+                    Grade::Again
+                };
+                AnswerCheck {
+                    grade,
+                    expected: expected.trim().to_owned(),
+                    distance,
+                }
+            };
+            if best
+                .as_ref()
+                .is_none_or(|best| check.distance < best.distance)
+            {
+                best = Some(check);
+            }
+        }
+        best
+    }
+
+    ///Returns whether the card may be presented at the specified time
     pub fn is_presentable(
         &self,
-        now: Option<&NaiveDateTime>,
+        now: &NaiveDateTime,
         decks: Option<&Vec<u8>>,
         due_only: bool,
         seen_only: bool,
     ) -> bool {
-        let now: NaiveDateTime = match now {
-            Some(dt) => *dt,
-            None => NaiveDateTime::from_timestamp(
-                SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Unable to get time")
-                    .as_secs() as i64,
-                0,
-            ),
-        };
+        if self.state.suspended || self.state.is_buried(now) {
+            return false;
+        }
         if decks.is_none() || decks.unwrap().contains(&self.deck) {
             if self.due.is_none() && seen_only {
                 return false;
             }
-            if !due_only || (due_only && (self.due.is_none() || self.due.unwrap() < now)) {
+            if !due_only || self.due.is_none() || self.due.unwrap() < *now {
                 return true;
             }
         }
@@ -703,40 +2306,2079 @@ impl VocaCard {
     }
 }
 
-pub fn load_files(files: Vec<&str>, force: bool, reset: bool) -> Vec<VocaData> {
-    let mut datasets: Vec<VocaData> = Vec::new();
+impl CardState {
+    ///Returns whether the card is buried at the specified time
+    pub fn is_buried(&self, now: &NaiveDateTime) -> bool {
+        self.buried.is_some_and(|buried| local_date(now) < buried)
+    }
+}
 
-    for filename in files.iter() {
-        if !PathBuf::from(filename).exists() {
-            eprintln!("ERROR: Specified input file not does exist: {}", filename);
-            std::process::exit(1);
-        } else {
-            match VocaData::from_file(filename, reset) {
-                Ok(mut data) => {
-                    if !datasets.is_empty() {
-                        if data.session.columns != datasets[0].session.columns {
-                            eprintln!("ERROR: columns of {} differ from those in the first loaded file, unable to load together.", filename);
-                            std::process::exit(1);
-                        }
-                        if data.session.decks != datasets[0].session.decks {
-                            if force || data.session.decks.is_empty() {
-                                data.session.decks = datasets[0].session.decks.clone();
-                                data.session.intervals = datasets[0].session.intervals.clone();
-                            } else {
-                                eprintln!("ERROR: decks of {} differ from those in the first loaded file, refusing to load together (use --force to force it)", filename);
-                                std::process::exit(1);
-                            }
-                        }
+impl fmt::Display for CardState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut states: Vec<String> = Vec::new();
+        if self.suspended {
+            states.push("suspended".to_owned());
+        }
+        if self.flagged {
+            states.push("flagged".to_owned());
+        }
+        if self.leech {
+            states.push("leech".to_owned());
+        }
+        if let Some(buried) = self.buried {
+            states.push(format!("buried:{}", buried.format("%Y-%m-%d")));
+        }
+        write!(f, "{}", states.join(","))
+    }
+}
+
+impl std::str::FromStr for CardState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut state = CardState::default();
+        for item in s.split(',').map(|item| item.trim()) {
+            match item {
+                "" => {}
+                "suspended" => state.suspended = true,
+                "flagged" => state.flagged = true,
+                "leech" => state.leech = true,
+                _ => match item.strip_prefix("buried:") {
+                    Some(date) => {
+                        state.buried = Some(
+                            NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                                .map_err(|e| format!("invalid burial date: {}", e))?,
+                        )
                     }
-                    datasets.push(data);
-                }
-                Err(err) => {
-                    eprintln!("ERROR loading {}: {}", filename, err);
-                    std::process::exit(1);
-                }
+                    None => return Err(format!("invalid card state: {}", item)),
+                },
             }
         }
+        Ok(state)
     }
+}
+
+impl fmt::Display for DailyCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let date = self
+            .date
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        write!(f, "{},{},{}", date, self.new, self.reviews)
+    }
+}
 
-    datasets
+impl std::str::FromStr for DailyCounts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let items: Vec<&str> = s.split(',').map(|item| item.trim()).collect();
+        if items.len() != 3 {
+            return Err(format!("expected date,new,reviews, got {}", s));
+        }
+        Ok(DailyCounts {
+            date: Some(
+                NaiveDate::parse_from_str(items[0], "%Y-%m-%d")
+                    .map_err(|e| format!("invalid date: {}", e))?,
+            ),
+            new: items[1]
+                .parse()
+                .map_err(|e| format!("invalid number of new cards: {}", e))?,
+            reviews: items[2]
+                .parse()
+                .map_err(|e| format!("invalid number of reviews: {}", e))?,
+        })
+    }
+}
+
+impl VariantState {
+    ///Serialises the state as tab-prefixed special columns, only columns that have a value are written
+    pub fn write_to_string(&self, reset: bool) -> String {
+        let mut result: String = String::new();
+        if !reset {
+            if self.schedule.deck > 0 {
+                result += format!("\t{}:deck#{}", self.label, self.schedule.deck + 1).as_str();
+            }
+            if let Some(due) = self.schedule.due {
+                result +=
+                    format!("\t{}:due@{}", self.label, due.format("%Y-%m-%d %H:%M:%S")).as_str();
+            }
+            if let Some(sm2) = self.schedule.sm2 {
+                result += format!(
+                    "\t{0}:ease@{1:.2}\t{0}:interval@{2}\t{0}:reps@{3}",
+                    self.label, sm2.ease, sm2.interval, sm2.repetitions
+                )
+                .as_str();
+            }
+            if let Some(fsrs) = self.schedule.fsrs {
+                result += format!(
+                    "\t{0}:stability@{1:.4}\t{0}:difficulty@{2:.4}",
+                    self.label, fsrs.stability, fsrs.difficulty
+                )
+                .as_str();
+                if let Some(last) = fsrs.last {
+                    result += format!("\t{}:last@{}", self.label, last.format("%Y-%m-%d %H:%M:%S"))
+                        .as_str();
+                }
+            }
+            if self.lapses > 0 {
+                result += format!("\t{}:lapses@{}", self.label, self.lapses).as_str();
+            }
+        }
+        if self.state != CardState::default() {
+            result += format!("\t{}:state@{}", self.label, self.state).as_str();
+        }
+        if let Some(id) = &self.id {
+            result += format!("\t{}:id@{}", self.label, id).as_str();
+        }
+        result
+    }
+}
+
+impl fmt::Display for ReviewAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReviewAction::Graded(grade) => write!(f, "{}", grade),
+            ReviewAction::Moved => write!(f, "move"),
+            ReviewAction::Skipped => write!(f, "skip"),
+            ReviewAction::Undone(None) => write!(f, "undo"),
+            ReviewAction::Undone(Some(timestamp)) => {
+                write!(f, "undo@{}", timestamp.format("%Y-%m-%d %H:%M:%S"))
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for ReviewAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "again" => Ok(ReviewAction::Graded(Grade::Again)),
+            "hard" => Ok(ReviewAction::Graded(Grade::Hard)),
+            "good" => Ok(ReviewAction::Graded(Grade::Good)),
+            "easy" => Ok(ReviewAction::Graded(Grade::Easy)),
+            "move" => Ok(ReviewAction::Moved),
+            "skip" => Ok(ReviewAction::Skipped),
+            "undo" => Ok(ReviewAction::Undone(None)),
+            _ => match s.strip_prefix("undo@") {
+                Some(timestamp) => NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+                    .map(|timestamp| ReviewAction::Undone(Some(timestamp)))
+                    .map_err(|e| format!("invalid time of undone action: {}", e)),
+                None => Err(format!("invalid review action: {}", s)),
+            },
+        }
+    }
+}
+
+impl ReviewLogEntry {
+    pub fn parse_line(line: &str, linenr: usize) -> Result<Self, VocaError> {
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 7 {
+            return Err(VocaError::parse(
+                linenr,
+                format!("expected 7 columns in review log, got {}", fields.len()),
+            ));
+        }
+        let timestamp = NaiveDateTime::parse_from_str(fields[0], "%Y-%m-%d %H:%M:%S")
+            .map_err(|e| VocaError::parse(linenr, format!("unable to parse timestamp: {}", e)))?;
+        let olddeck: u8 = parse_state_value(fields[2], "old deck", linenr)?;
+        let newdeck: u8 = parse_state_value(fields[3], "new deck", linenr)?;
+        Ok(ReviewLogEntry {
+            timestamp,
+            card: fields[1].to_owned(),
+            olddeck: olddeck.saturating_sub(1),
+            newdeck: newdeck.saturating_sub(1),
+            action: parse_state_value(fields[4], "action", linenr)?,
+            duration: parse_state_value(fields[5], "duration", linenr)?,
+            side: parse_state_value(fields[6], "side", linenr)?,
+        })
+    }
+
+    pub fn write_to_string(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.card,
+            self.olddeck + 1,
+            self.newdeck + 1,
+            self.action,
+            self.duration,
+            self.side
+        )
+    }
+}
+
+impl ReviewLog {
+    pub fn new(filename: &str) -> Self {
+        ReviewLog {
+            filename: filename.to_owned(),
+        }
+    }
+
+    ///Returns the review log that accompanies the specified vocabulary file
+    pub fn sidecar(filename: &str) -> Self {
+        Self::new(format!("{}.log", filename).as_str())
+    }
+
+    ///Appends an entry to the log, the file is created if it does not exist yet
+    pub fn append(&self, entry: &ReviewLogEntry) -> Result<(), std::io::Error> {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.filename.as_str())?;
+        file.write_all(entry.write_to_string().as_bytes())?;
+        file.write_all(b"\n")?;
+        Ok(())
+    }
+
+    ///Reads all entries from the log, a log that does not exist yet is considered empty
+    pub fn read(&self) -> Result<Vec<ReviewLogEntry>, VocaError> {
+        if !PathBuf::from(self.filename.as_str()).exists() {
+            return Ok(Vec::new());
+        }
+        let reader = BufReader::new(File::open(self.filename.as_str())?);
+        let mut entries: Vec<ReviewLogEntry> = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if !line.is_empty() && !line.starts_with('#') {
+                entries.push(
+                    ReviewLogEntry::parse_line(&line, i + 1)
+                        .map_err(|e| e.in_file(self.filename.as_str()))?,
+                );
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl Forecast {
+    ///Computes a forecast for the specified number of days, starting today
+    pub fn new(datasets: &[VocaData], now: &NaiveDateTime, days: usize) -> Self {
+        let start = local_date(now);
+        let mut forecast = Forecast {
+            start,
+            reviews: vec![0; days],
+            projected: vec![0; days],
+        };
+        let day =
+            |due: &NaiveDateTime| -> usize { (local_date(due) - start).num_days().max(0) as usize };
+        for dataset in datasets.iter() {
+            let scheduler = dataset.session.scheduler();
+            for card in dataset.cards.iter().filter(|card| !card.state.suspended) {
+                if let Some(due) = card.due {
+                    let index = day(&due);
+                    if let Some(count) = forecast.reviews.get_mut(index) {
+                        *count += 1;
+                    }
+                    let projected_due = if index == 0 {
+                        scheduler
+                            .schedule(card, Grade::Good, now, &dataset.session)
+                            .due
+                            .unwrap_or(due)
+                    } else {
+                        due
+                    };
+                    if let Some(count) = forecast.projected.get_mut(day(&projected_due)) {
+                        *count += 1;
+                    }
+                }
+            }
+        }
+        forecast
+    }
+
+    ///Returns the date of the n'th day of the forecast
+    pub fn date(&self, day: usize) -> NaiveDate {
+        self.start + Duration::days(day as i64)
+    }
+}
+
+impl VocaStats {
+    ///Computes statistics over the specified datasets at the specified time
+    pub fn new(datasets: &[VocaData], now: &NaiveDateTime) -> Self {
+        let mut stats = VocaStats::default();
+        let day = *now + Duration::days(1);
+        let week = *now + Duration::days(7);
+        let month = *now + Duration::days(30);
+        for dataset in datasets.iter() {
+            if stats.decks.len() < dataset.session.decks.len() {
+                stats.decks.resize(dataset.session.decks.len(), 0);
+            }
+            for card in dataset.cards.iter() {
+                stats.total += 1;
+                if stats.decks.len() <= card.deck as usize {
+                    stats.decks.resize(card.deck as usize + 1, 0);
+                }
+                stats.decks[card.deck as usize] += 1;
+                if card.state.flagged {
+                    stats.flagged += 1;
+                }
+                if card.state.suspended {
+                    stats.suspended += 1;
+                }
+                if card.state.leech {
+                    stats.leeches += 1;
+                }
+                if card.due.is_none() {
+                    stats.unseen += 1;
+                    continue;
+                }
+                stats.seen += 1;
+                if card.is_presentable(now, None, true, true) {
+                    stats.overdue += 1;
+                } else if card.is_presentable(&day, None, true, true) {
+                    stats.due_day += 1;
+                } else if card.is_presentable(&week, None, true, true) {
+                    stats.due_week += 1;
+                } else if card.is_presentable(&month, None, true, true) {
+                    stats.due_month += 1;
+                }
+            }
+        }
+        //make the due counts cumulative
+        stats.due_week += stats.due_day;
+        stats.due_month += stats.due_week;
+        stats
+    }
+
+    ///Adds the entries of a review log to the statistics, to compute retention
+    pub fn add_log(&mut self, entries: &[ReviewLogEntry]) {
+        //discard actions that were undone, only grades and moves can be undone (skips can not)
+        let mut kept: Vec<&ReviewLogEntry> = Vec::with_capacity(entries.len());
+        for entry in entries.iter() {
+            if let ReviewAction::Undone(undone) = entry.action {
+                if let Some(index) = kept.iter().rposition(|kept| {
+                    kept.card == entry.card
+                        && matches!(kept.action, ReviewAction::Graded(_) | ReviewAction::Moved)
+                        && undone.is_none_or(|timestamp| kept.timestamp == timestamp)
+                }) {
+                    kept.remove(index);
+                }
+            } else {
+                kept.push(entry);
+            }
+        }
+        let mut seen: HashSet<&str> = HashSet::new();
+        for entry in kept {
+            if let ReviewAction::Graded(grade) = entry.action {
+                self.reviews += 1;
+                //the first review of a card is not a test of retention
+                if !seen.insert(entry.card.as_str()) {
+                    self.recallable += 1;
+                    if grade != Grade::Again {
+                        self.recalled += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    ///Retention rate: the fraction of reviews of previously seen cards that were answered right
+    pub fn retention(&self) -> Option<f64> {
+        if self.recallable > 0 {
+            Some(self.recalled as f64 / self.recallable as f64)
+        } else {
+            None
+        }
+    }
+
+    pub fn print(&self, session: &VocaSession, format: PrintFormat) {
+        let label = |label: &str| -> String {
+            match format {
+                PrintFormat::Plain => format!("{}:", label),
+                PrintFormat::AnsiColour => Colour::Green.paint(format!("{}:", label)).to_string(),
+            }
+        };
+        println!("{} {}", label("Total"), self.total);
+        for (i, count) in self.decks.iter().enumerate() {
+            println!(
+                "{} {}",
+                label(
+                    format!(
+                        "Deck {} ({})",
+                        i + 1,
+                        session.decks.get(i).map(|s| s.as_str()).unwrap_or("none")
+                    )
+                    .as_str()
+                ),
+                count
+            );
+        }
+        println!("{} {}", label("Unseen"), self.unseen);
+        println!("{} {}", label("Seen"), self.seen);
+        println!("{} {}", label("Suspended"), self.suspended);
+        println!("{} {}", label("Flagged"), self.flagged);
+        println!("{} {}", label("Leeches"), self.leeches);
+        println!("{} {}", label("Due now"), self.overdue);
+        println!("{} {}", label("Due within a day"), self.due_day);
+        println!("{} {}", label("Due within a week"), self.due_week);
+        println!("{} {}", label("Due within a month"), self.due_month);
+        println!("{} {}", label("Reviews"), self.reviews);
+        if let Some(retention) = self.retention() {
+            println!("{} {:.1}%", label("Retention"), retention * 100.0);
+        } else {
+            println!("{} -", label("Retention"));
+        }
+    }
+
+    pub fn to_json(&self, session: &VocaSession) -> String {
+        let decks: Vec<String> = self
+            .decks
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                format!(
+                    "{{\"deck\": {}, \"name\": {}, \"cards\": {}}}",
+                    i + 1,
+                    session
+                        .decks
+                        .get(i)
+                        .map(|s| json_string(s))
+                        .unwrap_or_else(|| "null".to_owned()),
+                    count
+                )
+            })
+            .collect();
+        format!(
+            "{{\"total\": {}, \"decks\": [{}], \"unseen\": {}, \"seen\": {}, \"suspended\": {}, \"flagged\": {}, \"leeches\": {}, \"due_now\": {}, \"due_day\": {}, \"due_week\": {}, \"due_month\": {}, \"reviews\": {}, \"retention\": {}}}",
+            self.total,
+            decks.join(", "),
+            self.unseen,
+            self.seen,
+            self.suspended,
+            self.flagged,
+            self.leeches,
+            self.overdue,
+            self.due_day,
+            self.due_week,
+            self.due_month,
+            self.reviews,
+            self.retention()
+                .map(|r| format!("{:.4}", r))
+                .unwrap_or_else(|| "null".to_owned())
+        )
+    }
+}
+
+impl CardFilter {
+    ///Parses a filter query, column and deck names are resolved against the session
+    pub fn parse(query: &str, session: &VocaSession) -> Result<Self, VocaError> {
+        let mut terms: Vec<(bool, FilterTerm)> = Vec::new();
+        for token in split_query(query) {
+            let (negated, token) = match token.strip_prefix('-') {
+                Some(token) if !token.is_empty() => (true, token),
+                _ => (false, token.as_str()),
+            };
+            terms.push((negated, FilterTerm::parse(token, session)?));
+        }
+        Ok(CardFilter { terms })
+    }
+
+    ///Returns whether the card matches all terms of the filter
+    pub fn matches(&self, card: &VocaCard, session: &VocaSession, now: &NaiveDateTime) -> bool {
+        self.terms
+            .iter()
+            .all(|(negated, term)| term.matches(card, session, now) != *negated)
+    }
+}
+
+impl FilterTerm {
+    ///Parses a single (non-negated) term of a filter query
+    pub fn parse(term: &str, session: &VocaSession) -> Result<Self, VocaError> {
+        let error = |message: String| {
+            VocaError::InvalidArgument(format!("invalid filter term '{}': {}", term, message))
+        };
+        match term.find([':', '=', '~']) {
+            Some(pos) => {
+                let (key, value) = (&term[..pos], &term[pos + 1..]);
+                match &term[pos..pos + 1] {
+                    ":" => {
+                        let (comparison, value) = Comparison::parse(value);
+                        match key {
+                            "deck" => {
+                                let deck = match value.parse::<u8>() {
+                                    Ok(deck) if deck >= 1 => deck - 1,
+                                    _ => session
+                                        .get_deck_by_name(value)
+                                        .ok_or_else(|| error("no such deck".to_owned()))?,
+                                };
+                                Ok(FilterTerm::Deck(comparison, deck))
+                            }
+                            "due" => Ok(FilterTerm::Due(
+                                comparison,
+                                parse_duration(value).ok_or_else(|| {
+                                    error("expected a duration like 30m, 12h, 7d or 2w".to_owned())
+                                })?,
+                            )),
+                            "tag" => Ok(FilterTerm::Tags(
+                                value
+                                    .split(',')
+                                    .map(|tag| tag.trim().to_owned())
+                                    .filter(|tag| !tag.is_empty())
+                                    .collect(),
+                            )),
+                            _ => Err(error(format!("unknown key '{}'", key))),
+                        }
+                    }
+                    operator => {
+                        let column = session
+                            .columns
+                            .iter()
+                            .position(|column| column == key)
+                            .ok_or_else(|| error(format!("no such column '{}'", key)))?
+                            as u8;
+                        if operator == "=" {
+                            Ok(FilterTerm::FieldEquals(column, value.to_lowercase()))
+                        } else {
+                            Ok(FilterTerm::FieldContains(column, value.to_lowercase()))
+                        }
+                    }
+                }
+            }
+            None => match term {
+                "due" => Ok(FilterTerm::DueNow),
+                "seen" => Ok(FilterTerm::Seen),
+                "unseen" => Ok(FilterTerm::Unseen),
+                "suspended" => Ok(FilterTerm::Suspended),
+                "buried" => Ok(FilterTerm::Buried),
+                "flagged" => Ok(FilterTerm::Flagged),
+                "leech" => Ok(FilterTerm::Leech),
+                _ => Err(error("unknown term".to_owned())),
+            },
+        }
+    }
+
+    ///Returns whether the card matches this term
+    pub fn matches(&self, card: &VocaCard, session: &VocaSession, now: &NaiveDateTime) -> bool {
+        match self {
+            FilterTerm::Deck(comparison, deck) => comparison.compare(card.deck, *deck),
+            FilterTerm::Due(comparison, minutes) => card
+                .due
+                .is_some_and(|due| comparison.compare((due - *now).num_minutes(), *minutes)),
+            FilterTerm::FieldEquals(column, value) => {
+                card.fields.get(*column as usize).is_some_and(|field| {
+                    let field = field.to_lowercase();
+                    match &session.listdelimiter {
+                        Some(listdelimiter) => field
+                            .split(listdelimiter.as_str())
+                            .any(|item| item.trim() == value),
+                        None => field.trim() == value,
+                    }
+                })
+            }
+            FilterTerm::FieldContains(column, value) => card
+                .fields
+                .get(*column as usize)
+                .is_some_and(|field| field.to_lowercase().contains(value.as_str())),
+            FilterTerm::Tags(tags) => tags.iter().any(|tag| card.has_tag(tag, session)),
+            FilterTerm::DueNow => card.due.is_none_or(|due| due < *now),
+            FilterTerm::Seen => card.due.is_some(),
+            FilterTerm::Unseen => card.due.is_none(),
+            FilterTerm::Suspended => card.state.suspended,
+            FilterTerm::Buried => card.state.is_buried(now),
+            FilterTerm::Flagged => card.state.flagged,
+            FilterTerm::Leech => card.state.leech,
+        }
+    }
+}
+
+impl Comparison {
+    ///Parses the comparison operator at the start of a value (equality if there is none), returns the operator and the remainder
+    pub fn parse(value: &str) -> (Self, &str) {
+        for (prefix, comparison) in [
+            (">=", Comparison::GreaterOrEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">", Comparison::Greater),
+            ("<", Comparison::Less),
+            ("=", Comparison::Equal),
+        ] {
+            if let Some(value) = value.strip_prefix(prefix) {
+                return (comparison, value);
+            }
+        }
+        (Comparison::Equal, value)
+    }
+
+    ///Compares a value against a reference value
+    pub fn compare<T: PartialOrd>(&self, value: T, reference: T) -> bool {
+        match self {
+            Comparison::Equal => value == reference,
+            Comparison::Less => value < reference,
+            Comparison::LessOrEqual => value <= reference,
+            Comparison::Greater => value > reference,
+            Comparison::GreaterOrEqual => value >= reference,
+        }
+    }
+}
+
+///Picks distractors for a multiple-choice question about the specified card: distinct values of
+///the specified column (or gaps, for cloze deletion cards) taken from other cards, preferably from cards on the same deck
+pub fn pick_distractors(
+    datasets: &[VocaData],
+    setindex: usize,
+    cardindex: usize,
+    column: u8,
+    count: usize,
+    rng: &mut impl Rng,
+) -> Vec<String> {
+    let card = &datasets[setindex].cards[cardindex];
+    let mut seen: HashSet<String> = HashSet::new();
+    if let Some(answer) = card.answer(column) {
+        seen.insert(normalize_answer(&answer, false));
+    }
+    let mut samedeck: Vec<String> = Vec::new();
+    let mut otherdecks: Vec<String> = Vec::new();
+    for dataset in datasets.iter() {
+        for other in dataset.cards.iter() {
+            if other.cloze.is_some() != card.cloze.is_some() {
+                //gaps are only confused with other gaps
+                continue;
+            }
+            if let Some(value) = other.answer(column) {
+                if !value.is_empty() && seen.insert(normalize_answer(&value, false)) {
+                    if other.deck == card.deck {
+                        samedeck.push(value);
+                    } else {
+                        otherdecks.push(value);
+                    }
+                }
+            }
+        }
+    }
+    samedeck.shuffle(rng);
+    otherdecks.shuffle(rng);
+    samedeck.into_iter().chain(otherdecks).take(count).collect()
+}
+
+///Normalises an answer for comparison: lowercases it and collapses whitespace, optionally also
+///strips diacritics from Latin characters
+fn normalize_answer(s: &str, fold: bool) -> String {
+    s.split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .to_lowercase()
+        .chars()
+        .map(|c| if fold { fold_diacritic(c) } else { c })
+        .collect()
+}
+
+///Maps a lowercase (Latin) character with diacritics to its base character
+fn fold_diacritic(c: char) -> char {
+    const TABLE: &[(&str, char)] = &[
+        ("àáâãäåāăąǎ", 'a'),
+        ("çćĉċč", 'c'),
+        ("ďđ", 'd'),
+        ("èéêëēĕėęě", 'e'),
+        ("ĝğġģ", 'g'),
+        ("ĥħ", 'h'),
+        ("ìíîïĩīĭįıǐ", 'i'),
+        ("ĵ", 'j'),
+        ("ķ", 'k'),
+        ("ĺļľŀł", 'l'),
+        ("ñńņňŉ", 'n'),
+        ("òóôõöøōŏőǒ", 'o'),
+        ("ŕŗř", 'r'),
+        ("śŝşšș", 's'),
+        ("ţťŧț", 't'),
+        ("ùúûüũūŭůűųǔǖǘǚǜ", 'u'),
+        ("ŵ", 'w'),
+        ("ýÿŷ", 'y'),
+        ("źżž", 'z'),
+    ];
+    if c.is_ascii() {
+        return c;
+    }
+    for (chars, base) in TABLE.iter() {
+        if chars.contains(c) {
+            return *base;
+        }
+    }
+    c
+}
+
+///Computes the Levenshtein edit distance between two strings (in characters)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current: Vec<usize> = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+///Encodes a string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            c if (c as u32) < 0x20 => result += format!("\\u{:04x}", c as u32).as_str(),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+///Computes a short identifier from a text (32-bit FNV-1a hash, hexadecimal)
+fn hash_id(text: &str) -> String {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in text.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    format!("{:08x}", hash)
+}
+
+///Parses the value of a special column holding scheduling state
+fn parse_state_value<T>(value: &str, name: &str, linenr: usize) -> Result<T, VocaError>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    value
+        .parse::<T>()
+        .map_err(|e| VocaError::parse(linenr, format!("unable to parse {}: {}", name, e)))
+}
+
+///Splits a filter query into terms on whitespace, double quotes group words into a single term
+fn split_query(query: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in query.chars() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c.is_whitespace() && !quoted {
+            if !token.is_empty() {
+                tokens.push(std::mem::take(&mut token));
+            }
+        } else {
+            token.push(c);
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+///Parses a duration such as ``30m``, ``12h``, ``7d`` or ``2w`` into minutes, a plain number is in days
+fn parse_duration(value: &str) -> Option<i64> {
+    let (number, unit) = match value.find(|c: char| c.is_alphabetic()) {
+        Some(pos) => (&value[..pos], &value[pos..]),
+        None => (value, "d"),
+    };
+    let number = number.trim().parse::<i64>().ok()?;
+    //an overflow is an invalid duration rather than a panic
+    match unit {
+        "m" | "min" => Some(number),
+        "h" => number.checked_mul(60),
+        "d" => number.checked_mul(1440),
+        "w" => number.checked_mul(10080),
+        _ => None,
+    }
+}
+
+///Parses a special column holding the state of a card into the specified variant state,
+///returns false if the value is not a special column
+fn parse_state_column(
+    value: &str,
+    variantstate: &mut VariantState,
+    reset: bool,
+    linenr: usize,
+) -> Result<bool, VocaError> {
+    let schedule = &mut variantstate.schedule;
+    if let Some(value) = value.strip_prefix("deck#") {
+        if !reset {
+            let num: u8 = parse_state_value(value, "deck", linenr)?;
+            if num == 0 {
+                return Err(VocaError::parse(
+                    linenr,
+                    "decks are numbered from 1, deck#0 does not exist".to_owned(),
+                ));
+            }
+            schedule.deck = num - 1;
+        }
+    } else if let Some(value) = value.strip_prefix("due@") {
+        if !reset {
+            schedule.due = match NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+                Ok(dt) => Some(dt),
+                Err(e) => {
+                    return Err(VocaError::parse(
+                        linenr,
+                        format!("unable to parse due date: {}", e),
+                    ));
+                }
+            };
+        }
+    } else if let Some(value) = value.strip_prefix("ease@") {
+        if !reset {
+            schedule.sm2.get_or_insert_with(Sm2State::default).ease =
+                parse_state_value(value, "ease factor", linenr)?;
+        }
+    } else if let Some(value) = value.strip_prefix("interval@") {
+        if !reset {
+            schedule.sm2.get_or_insert_with(Sm2State::default).interval =
+                parse_state_value(value, "interval", linenr)?;
+        }
+    } else if let Some(value) = value.strip_prefix("reps@") {
+        if !reset {
+            schedule
+                .sm2
+                .get_or_insert_with(Sm2State::default)
+                .repetitions = parse_state_value(value, "repetitions", linenr)?;
+        }
+    } else if let Some(value) = value.strip_prefix("id@") {
+        variantstate.id = Some(value.trim().to_owned());
+    } else if let Some(value) = value.strip_prefix("state@") {
+        variantstate.state = parse_state_value(value, "card state", linenr)?;
+    } else if let Some(value) = value.strip_prefix("lapses@") {
+        if !reset {
+            variantstate.lapses = parse_state_value(value, "lapses", linenr)?;
+        }
+    } else if let Some(value) = value.strip_prefix("stability@") {
+        if !reset {
+            schedule
+                .fsrs
+                .get_or_insert_with(FsrsState::default)
+                .stability = parse_state_value(value, "stability", linenr)?;
+        }
+    } else if let Some(value) = value.strip_prefix("difficulty@") {
+        if !reset {
+            schedule
+                .fsrs
+                .get_or_insert_with(FsrsState::default)
+                .difficulty = parse_state_value(value, "difficulty", linenr)?;
+        }
+    } else if let Some(value) = value.strip_prefix("last@") {
+        if !reset {
+            schedule.fsrs.get_or_insert_with(FsrsState::default).last =
+                match NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+                    Ok(dt) => Some(dt),
+                    Err(e) => {
+                        return Err(VocaError::parse(
+                            linenr,
+                            format!("unable to parse time of last review: {}", e),
+                        ));
+                    }
+                };
+        }
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+///Returns the number of data columns in a line split into values: special columns are only
+///recognised in the trailing positions, after the last value that is not a special column
+fn data_column_count(values: &[&str]) -> usize {
+    values
+        .iter()
+        .rposition(|value| !value.is_empty() && !is_state_column(value))
+        .map_or(0, |i| i + 1)
+}
+
+///Tests whether a value is a special column holding the state of a card or of one of its variants
+fn is_state_column(value: &str) -> bool {
+    STATE_PREFIXES
+        .iter()
+        .any(|prefix| value.starts_with(prefix))
+        || split_variant_label(value).is_some()
+}
+
+///Splits a special column holding the state of a variant (e.g. ``c2:deck#3``) into its label and the
+///regular special column
+fn split_variant_label(value: &str) -> Option<(&str, &str)> {
+    let (label, column) = value.split_once(':')?;
+    if !label.is_empty()
+        && label.chars().all(|c| c.is_ascii_alphanumeric())
+        && STATE_PREFIXES
+            .iter()
+            .any(|prefix| column.starts_with(prefix))
+    {
+        Some((label, column))
+    } else {
+        None
+    }
+}
+
+///Finds all cloze deletion gaps in a field. Gaps marked with square brackets are numbered in order of appearance.
+fn cloze_gaps(field: &str) -> Vec<ClozeGap<'_>> {
+    let mut gaps: Vec<ClozeGap> = Vec::new();
+    let mut bracketcount: u8 = 0;
+    let mut begin = 0;
+    while let Some(c) = field[begin..].chars().next() {
+        let rest = &field[begin..];
+        if let Some(markup) = rest.strip_prefix("{{c") {
+            if let Some(end) = markup.find("}}") {
+                if let Some((number, content)) = markup[..end].split_once("::") {
+                    if let Ok(number) = number.parse::<u8>() {
+                        let (answer, hint) = match content.split_once("::") {
+                            Some((answer, hint)) => (answer, Some(hint)),
+                            None => (content, None),
+                        };
+                        gaps.push(ClozeGap {
+                            begin,
+                            end: begin + 3 + end + 2,
+                            number,
+                            answer,
+                            hint,
+                        });
+                        begin += 3 + end + 2;
+                        continue;
+                    }
+                }
+            }
+        } else if c == '[' {
+            if let Some(end) = rest.find(']') {
+                bracketcount = match bracketcount.checked_add(1) {
+                    Some(count) => count,
+                    None => break, //no more gaps than can be numbered
+                };
+                gaps.push(ClozeGap {
+                    begin,
+                    end: begin + end + 1,
+                    number: bracketcount,
+                    answer: &rest[1..end],
+                    hint: None,
+                });
+                begin += end + 1;
+                continue;
+            }
+        }
+        begin += c.len_utf8();
+    }
+    gaps
+}
+
+///Renders a field with cloze deletions: the gaps with the specified number are blanked (showing the hint, if any),
+///or revealed in square brackets, all other gaps are filled in
+fn render_cloze(field: &str, number: u8, reveal: bool) -> String {
+    let mut result = String::new();
+    let mut begin = 0;
+    for gap in cloze_gaps(field) {
+        result += &field[begin..gap.begin];
+        if gap.number != number {
+            result += gap.answer;
+        } else if reveal {
+            result += format!("[{}]", gap.answer).as_str();
+        } else {
+            result += format!("[{}]", gap.hint.unwrap_or("...")).as_str();
+        }
+        begin = gap.end;
+    }
+    result += &field[begin..];
+    result
+}
+
+///Returns the current time (UTC), at a resolution of seconds. The library itself never calls this, all
+///functions that depend on the time take it as a parameter.
+pub fn current_time() -> NaiveDateTime {
+    Utc::now()
+        .naive_utc()
+        .with_nanosecond(0)
+        .expect("Unable to get time")
+}
+
+///Returns the local calendar date of a time (UTC), so days start at midnight in the local time zone
+pub fn local_date(time: &NaiveDateTime) -> NaiveDate {
+    Local.from_utc_datetime(time).date_naive()
+}
+
+///Loads the specified vocabulary files, all files must have the same columns and (unless forced) the same decks
+///Arguments (if specified) take precedence over the metadata in the files, after which the cards are split into their variants.
+pub fn load_files(
+    files: Vec<&str>,
+    force: bool,
+    reset: bool,
+    args: Option<&clap::ArgMatches>,
+) -> Result<Vec<VocaData>, VocaError> {
+    let mut datasets: Vec<VocaData> = Vec::new();
+    //identifiers must be unique over all datasets, as they share the review log
+    let mut ids: HashSet<String> = HashSet::new();
+
+    for filename in files.iter() {
+        if !PathBuf::from(filename).exists() {
+            return Err(VocaError::Io(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("specified input file does not exist: {}", filename),
+            )));
+        }
+        let mut data = VocaData::from_file(filename, reset)?;
+        if !datasets.is_empty() {
+            if data.session.columns != datasets[0].session.columns {
+                return Err(VocaError::IncompatibleDatasets {
+                    filename: filename.to_string(),
+                    reason: "the columns differ from those in the first loaded file".to_owned(),
+                });
+            }
+            if data.session.decks != datasets[0].session.decks {
+                if force || data.session.decks.is_empty() {
+                    data.session.decks = datasets[0].session.decks.clone();
+                    data.session.intervals = datasets[0].session.intervals.clone();
+                } else {
+                    return Err(VocaError::IncompatibleDatasets {
+                        filename: filename.to_string(),
+                        reason: "the decks differ from those in the first loaded file (use --force to force it)".to_owned(),
+                    });
+                }
+            }
+        }
+        if let Some(args) = args {
+            data.session.set_common_arguments(args)?;
+        }
+        data.expand();
+        data.assign_ids_except(&mut ids);
+        datasets.push(data);
+    }
+
+    Ok(datasets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn time(s: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").expect("valid time")
+    }
+
+    ///Returns the time (UTC) of the specified hour on a day in the local time zone, for tests that depend on where days begin
+    fn local_time(date: NaiveDate, hour: u32) -> NaiveDateTime {
+        Local
+            .from_local_datetime(&date.and_hms_opt(hour, 0, 0).expect("valid time"))
+            .earliest()
+            .expect("valid local time")
+            .naive_utc()
+    }
+
+    fn now() -> NaiveDateTime {
+        time("2021-03-01 12:00:00")
+    }
+
+    fn session() -> VocaSession {
+        VocaSession::from_arguments(vec![
+            "--columns",
+            "word,translation",
+            "--decks",
+            "new,daily,weekly,monthly",
+            "--intervals",
+            "0,1440,10080,43200",
+        ])
+        .expect("valid session")
+    }
+
+    fn card(word: &str, translation: &str) -> VocaCard {
+        VocaCard::parse_line(&format!("{}\t{}", word, translation), None, false, 1)
+            .expect("valid line")
+    }
+
+    fn dataset(cards: Vec<VocaCard>, session: VocaSession) -> VocaData {
+        VocaData {
+            session,
+            cards,
+            comments: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    fn entry(card: &str, action: ReviewAction) -> ReviewLogEntry {
+        ReviewLogEntry {
+            timestamp: now(),
+            card: card.to_owned(),
+            olddeck: 0,
+            newdeck: 0,
+            action,
+            duration: 0,
+            side: 0,
+        }
+    }
+
+    #[test]
+    fn leitner_schedule() {
+        let mut session = session();
+        let mut card = card("hond", "dog");
+        card.deck = 1;
+        let schedule = Leitner.schedule(&card, Grade::Good, &now(), &session);
+        assert_eq!(schedule.deck, 2);
+        assert_eq!(schedule.due, Some(time("2021-03-08 12:00:00")));
+        let schedule = Leitner.schedule(&card, Grade::Hard, &now(), &session);
+        assert_eq!(schedule.deck, 1);
+        assert_eq!(schedule.due, Some(time("2021-03-02 12:00:00")));
+        let schedule = Leitner.schedule(&card, Grade::Again, &now(), &session);
+        assert_eq!(schedule.deck, 0);
+        assert_eq!(schedule.due, Some(now()));
+        //easy moves two decks up, but not beyond the last deck
+        card.deck = 2;
+        let schedule = Leitner.schedule(&card, Grade::Easy, &now(), &session);
+        assert_eq!(schedule.deck, 3);
+        session.returntofirst = true;
+        let schedule = Leitner.schedule(&card, Grade::Again, &now(), &session);
+        assert_eq!(schedule.deck, 0);
+    }
+
+    #[test]
+    fn sm2_schedule() {
+        let session = session();
+        let mut card = card("hond", "dog");
+        let mut intervals = Vec::new();
+        for _ in 0..3 {
+            let schedule = Sm2.schedule(&card, Grade::Good, &now(), &session);
+            intervals.push(schedule.sm2.expect("sm2 state").interval);
+            card.set_schedule(schedule);
+        }
+        assert_eq!(intervals, vec![1, 6, 15]);
+        assert_eq!(card.due, Some(time("2021-03-16 12:00:00")));
+        let schedule = Sm2.schedule(&card, Grade::Again, &now(), &session);
+        let state = schedule.sm2.expect("sm2 state");
+        assert_eq!(state.repetitions, 0);
+        assert_eq!(state.interval, 0);
+        assert!((state.ease - 1.96).abs() < 1e-9);
+        assert_eq!(schedule.due, Some(now()));
+    }
+
+    #[test]
+    fn fsrs_schedule() {
+        let session = session();
+        let fsrs = session.fsrs();
+        let mut card = card("hond", "dog");
+        let schedule = fsrs.schedule(&card, Grade::Good, &now(), &session);
+        let state = schedule.fsrs.expect("fsrs state");
+        assert_eq!(state.stability, FSRS_DEFAULT_WEIGHTS[2]);
+        assert_eq!(state.difficulty, FSRS_DEFAULT_WEIGHTS[4]);
+        assert_eq!(state.last, Some(now()));
+        assert_eq!(schedule.due, Some(time("2021-03-05 12:00:00")));
+        card.set_schedule(schedule);
+
+        //a successful review on the due date increases the stability
+        let later = time("2021-03-05 12:00:00");
+        let schedule = fsrs.schedule(&card, Grade::Good, &later, &session);
+        let reviewed = schedule.fsrs.expect("fsrs state");
+        assert!(reviewed.stability > state.stability);
+        assert_eq!(reviewed.last, Some(later));
+
+        //the elapsed time is taken from the last review, not from the due date
+        let mut relearned = VocaCard::new(card.fields.clone());
+        relearned.set_schedule(card.schedule());
+        relearned.due = Some(later);
+        let schedule = fsrs.schedule(&relearned, Grade::Good, &later, &session);
+        assert_eq!(
+            schedule.fsrs.expect("fsrs state").stability,
+            reviewed.stability
+        );
+
+        //a wrong answer lowers the stability, the card is due right away
+        let schedule = fsrs.schedule(&card, Grade::Again, &later, &session);
+        assert!(schedule.fsrs.expect("fsrs state").stability <= state.stability);
+        assert_eq!(schedule.due, Some(later));
+
+        //an incomplete state without stability counts as a first review
+        card.fsrs = Some(FsrsState {
+            stability: 0.0,
+            difficulty: 5.0,
+            last: None,
+        });
+        let schedule = fsrs.schedule(&card, Grade::Good, &later, &session);
+        assert_eq!(
+            schedule.fsrs.expect("fsrs state").stability,
+            FSRS_DEFAULT_WEIGHTS[2]
+        );
+    }
+
+    #[test]
+    fn review_log_round_trip() {
+        let line = "2021-03-01 12:00:00\thond\t2\t3\tgood\t1500\t1";
+        let entry = ReviewLogEntry::parse_line(line, 1).expect("valid entry");
+        assert_eq!(entry.timestamp, now());
+        assert_eq!(entry.card, "hond");
+        assert_eq!((entry.olddeck, entry.newdeck), (1, 2));
+        assert_eq!(entry.action, ReviewAction::Graded(Grade::Good));
+        assert_eq!(entry.duration, 1500);
+        assert_eq!(entry.side, 1);
+        assert_eq!(entry.write_to_string(), line);
+        assert!(ReviewLogEntry::parse_line("2021-03-01 12:00:00\thond\t2\t3\tgood", 1).is_err());
+        assert!(
+            ReviewLogEntry::parse_line("2021-03-01 12:00:00\thond\t2\t3\tmaybe\t1500\t1", 1)
+                .is_err()
+        );
+
+        let path = std::env::temp_dir().join(format!("vocage-test-{}.log", std::process::id()));
+        let log = ReviewLog::new(path.to_str().expect("valid path"));
+        assert!(log.read().expect("empty log").is_empty());
+        log.append(&entry).expect("append");
+        log.append(&entry).expect("append");
+        let entries = log.read().expect("read log");
+        std::fs::remove_file(&path).expect("remove log");
+        assert_eq!(entries, vec![entry.clone(), entry]);
+    }
+
+    #[test]
+    fn assign_ids() {
+        let kept =
+            VocaCard::parse_line("kat\tcat\tid@0000cafe", None, false, 1).expect("valid line");
+        assert_eq!(kept.id.as_deref(), Some("0000cafe"));
+        let mut data = dataset(
+            vec![card("hond", "dog"), card("hond", "hound"), kept],
+            session(),
+        );
+        data.assign_ids();
+        let ids: Vec<String> = data
+            .cards
+            .iter()
+            .map(|card| card.id.clone().expect("assigned id"))
+            .collect();
+        assert_eq!(ids[0], hash_id("hond"));
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ids[2], "0000cafe");
+        assert_eq!(data.get_index_by_id(&ids[1]), Some(1));
+        assert_eq!(data.get_index_by_id("missing"), None);
+
+        //identifiers survive a write and parse
+        let line = data.cards[1].write_to_string(2, false);
+        assert!(line.ends_with(&format!("\tid@{}", ids[1])));
+        let parsed = VocaCard::parse_line(&line, None, false, 1).expect("valid line");
+        assert_eq!(parsed.id, Some(ids[1].clone()));
+
+        //copied lines and cards in other datasets with the same identifier get a new one
+        let copy =
+            VocaCard::parse_line("kat\tcat\tid@0000cafe", None, false, 2).expect("valid line");
+        data.cards.push(copy);
+        let mut taken: HashSet<String> = HashSet::new();
+        data.assign_ids_except(&mut taken);
+        assert_eq!(data.cards[2].id.as_deref(), Some("0000cafe"));
+        assert_ne!(data.cards[3].id.as_deref(), Some("0000cafe"));
+        assert_eq!(taken.len(), 4);
+        let mut other = dataset(vec![card("kat", "cat")], session());
+        other.cards[0].id = Some("0000cafe".to_owned());
+        other.assign_ids_except(&mut taken);
+        assert!(other.cards[0].id.is_some());
+        assert_ne!(other.cards[0].id.as_deref(), Some("0000cafe"));
+
+        //the same word in different files gets a different identifier
+        let mut first = dataset(vec![card("hond", "dog")], session());
+        first.session.filename = Some("first.tsv".to_owned());
+        first.assign_ids();
+        let mut second = dataset(vec![card("hond", "dog")], session());
+        second.session.filename = Some("second.tsv".to_owned());
+        second.assign_ids();
+        assert_ne!(first.cards[0].id, second.cards[0].id);
+
+        //special columns are only recognised after the data columns
+        let parsed = VocaCard::parse_line("id@home\tthuis\tid@0000cafe", Some(2), false, 1)
+            .expect("valid line");
+        assert_eq!(parsed.fields, vec!["id@home", "thuis"]);
+        assert_eq!(parsed.id.as_deref(), Some("0000cafe"));
+    }
+
+    #[test]
+    fn stats_counts_and_retention() {
+        let mut cards = vec![card("hond", "dog")];
+        for (deck, due) in [
+            (1, "2021-02-28 12:00:00"),
+            (1, "2021-03-02 00:00:00"),
+            (2, "2021-03-04 12:00:00"),
+            (3, "2021-03-21 12:00:00"),
+            (3, "2021-06-01 12:00:00"),
+        ]
+        .iter()
+        {
+            let mut card = card("kat", "cat");
+            card.deck = *deck;
+            card.due = Some(time(due));
+            cards.push(card);
+        }
+        let mut stats = VocaStats::new(&[dataset(cards, session())], &now());
+        assert_eq!(stats.total, 6);
+        assert_eq!(stats.decks, vec![1, 2, 1, 2]);
+        assert_eq!((stats.unseen, stats.seen), (1, 5));
+        assert_eq!(stats.overdue, 1);
+        //the due counts are cumulative
+        assert_eq!((stats.due_day, stats.due_week, stats.due_month), (1, 2, 3));
+        assert_eq!(stats.retention(), None);
+
+        let good = ReviewAction::Graded(Grade::Good);
+        stats.add_log(&[
+            entry("hond", good),
+            entry("hond", ReviewAction::Graded(Grade::Again)),
+            entry("kat", good),
+            entry("kat", ReviewAction::Skipped),
+            entry("hond", good),
+        ]);
+        //the first review of each card does not count towards retention
+        assert_eq!(stats.reviews, 4);
+        assert_eq!((stats.recalled, stats.recallable), (1, 2));
+        assert_eq!(stats.retention(), Some(0.5));
+    }
+
+    #[test]
+    fn forecast_buckets() {
+        let mut cards = vec![card("hond", "dog")];
+        //days start at local midnight
+        let today = local_date(&now());
+        let now = local_time(today, 12);
+        for (deck, day, hour) in [(1, -2, 12), (0, 0, 18), (2, 2, 8), (3, 31, 12)].iter() {
+            let mut card = card("kat", "cat");
+            card.deck = *deck;
+            card.due = Some(local_time(today + Duration::days(*day), *hour));
+            cards.push(card);
+        }
+        let forecast = Forecast::new(&[dataset(cards, session())], &now, 8);
+        //overdue cards count for today, unseen cards and cards beyond the forecast not at all
+        assert_eq!(forecast.reviews, vec![2, 0, 1, 0, 0, 0, 0, 0]);
+        //cards due today are projected to move up a deck
+        assert_eq!(forecast.projected, vec![0, 1, 1, 0, 0, 0, 0, 1]);
+        assert_eq!(forecast.date(2), today + Duration::days(2));
+    }
+
+    #[test]
+    fn check_answers() {
+        let mut session = session();
+        session.listdelimiter = Some(",".to_owned());
+        let grade = |card: &VocaCard, answer: &str| {
+            card.check_answer(answer, 1, &session)
+                .expect("answer check")
+                .grade
+        };
+        let house = card("huis", "house, home");
+        assert_eq!(grade(&house, "house"), Grade::Good);
+        assert_eq!(grade(&house, " Home "), Grade::Good);
+        assert_eq!(grade(&house, "garden"), Grade::Again);
+        assert_eq!(grade(&house, ""), Grade::Again);
+        //a single edit in a short answer easily yields another word
+        assert_eq!(grade(&house, "mouse"), Grade::Again);
+        assert_eq!(grade(&house, "hous"), Grade::Again);
+        let garden = card("tuin", "garden");
+        assert_eq!(grade(&garden, "gardn"), Grade::Hard);
+        //no typos are tolerated in short answers
+        let cat = card("kat", "cat");
+        assert_eq!(grade(&cat, "cat"), Grade::Good);
+        assert_eq!(grade(&cat, "car"), Grade::Again);
+        assert_eq!(grade(&cat, "ca"), Grade::Again);
+        assert_eq!(grade(&cat, ""), Grade::Again);
+        //answers that differ only in accents
+        let coffee = card("koffie", "café");
+        assert_eq!(grade(&coffee, "cafe"), Grade::Hard);
+        assert_eq!(
+            coffee
+                .check_answer("cafe", 1, &session)
+                .expect("answer check")
+                .expected,
+            "café"
+        );
+        //only the answer column is checked, not the other columns on the back side
+        let mut session = VocaSession::from_arguments(vec![
+            "--columns",
+            "word,translation,example",
+            "--showcolumns",
+            "word",
+            "--showcolumns",
+            "translation,example",
+        ])
+        .expect("valid session");
+        session.listdelimiter = Some(",".to_owned());
+        let house =
+            VocaCard::parse_line("huis\thouse, home\tmouse", None, false, 1).expect("valid line");
+        let grade = |answer: &str| {
+            house
+                .check_answer(answer, 1, &session)
+                .expect("answer check")
+                .grade
+        };
+        assert_eq!(grade("home"), Grade::Good);
+        assert_eq!(grade("mouse"), Grade::Again);
+    }
+
+    #[test]
+    fn distractors() {
+        let mut cards = Vec::new();
+        for (word, translation, deck) in [
+            ("hond", "dog", 1),
+            ("kat", "cat", 1),
+            ("vis", "fish", 0),
+            ("hond", "Dog", 1),
+            ("poes", "cat", 2),
+            ("paard", "", 1),
+        ]
+        .iter()
+        {
+            let mut card = card(word, translation);
+            card.deck = *deck;
+            cards.push(card);
+        }
+        let datasets = vec![dataset(cards, session())];
+        let mut rng = StdRng::seed_from_u64(1);
+        //distinct values other than the answer, cards on the same deck come first
+        assert_eq!(
+            pick_distractors(&datasets, 0, 0, 1, 2, &mut rng),
+            vec!["cat", "fish"]
+        );
+        assert_eq!(pick_distractors(&datasets, 0, 0, 1, 10, &mut rng).len(), 2);
+        let words = pick_distractors(&datasets, 0, 0, 0, 1, &mut rng);
+        assert!(words == vec!["kat"] || words == vec!["paard"]);
+    }
+
+    #[test]
+    fn cloze_gaps_and_rendering() {
+        let field = "De [hond] eet een {{c3::bot::noun}} en [vlees]";
+        let gaps = cloze_gaps(field);
+        let gaps: Vec<(u8, &str, Option<&str>)> = gaps
+            .iter()
+            .map(|gap| (gap.number, gap.answer, gap.hint))
+            .collect();
+        assert_eq!(
+            gaps,
+            vec![
+                (1, "hond", None),
+                (3, "bot", Some("noun")),
+                (2, "vlees", None)
+            ]
+        );
+        assert_eq!(
+            render_cloze(field, 3, false),
+            "De hond eet een [noun] en vlees"
+        );
+        assert_eq!(
+            render_cloze(field, 1, false),
+            "De [...] eet een bot en vlees"
+        );
+        assert_eq!(
+            render_cloze(field, 1, true),
+            "De [hond] eet een bot en vlees"
+        );
+        //incomplete markup is left as it is
+        assert!(cloze_gaps("{{c1::hond} [kat").is_empty());
+        //there are no more bracket gaps than can be numbered
+        assert_eq!(cloze_gaps(&"[x]".repeat(300)).len(), 255);
+    }
+
+    #[test]
+    fn expand_clozes() {
+        let parsed = VocaCard::parse_line(
+            "De [hond] eet een {{c3::bot::noun}}\tthe dog eats a bone\tc3:deck#2",
+            None,
+            false,
+            1,
+        )
+        .expect("valid line");
+        let cards = parsed.expand_clozes(0);
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].variant, None);
+        assert_eq!(cards[0].cloze.map(|cloze| cloze.number), Some(1));
+        assert_eq!(cards[0].cloze_answer().as_deref(), Some("hond"));
+        assert_eq!(cards[1].variant.as_deref(), Some("c3"));
+        assert_eq!(cards[1].cloze.map(|cloze| cloze.number), Some(3));
+        assert_eq!(cards[1].cloze_answer().as_deref(), Some("bot"));
+        assert_eq!(cards[1].deck, 1);
+        assert!(cards[0].variantstates.is_empty());
+
+        //gaps with the same number belong to the same card
+        let cards = card("{{c1::de}} {{c2::hond}} {{c1::eet}}", "").expand_clozes(0);
+        assert_eq!(cards.len(), 2);
+        assert_eq!(cards[0].cloze_answer().as_deref(), Some("de eet"));
+
+        //cards without clozes are left as they are
+        let cards = card("hond", "dog").expand_clozes(0);
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].cloze, None);
+    }
+
+    #[test]
+    fn parse_and_write_line() {
+        let line = "hond\tdog\tdeck#3\tdue@2021-03-01 12:00:00\tstability@3.7145\tdifficulty@5.1618\tlast@2021-02-25 12:00:00\tlapses@2\tstate@suspended,flagged\tid@c1cae662\tc2:deck#2\tc2:due@2021-03-02 12:00:00\tc2:lapses@1\tc2:state@leech,buried:2021-03-02";
+        let card = VocaCard::parse_line(line, None, false, 1).expect("valid line");
+        assert_eq!(card.fields, vec!["hond", "dog"]);
+        assert_eq!(card.deck, 2);
+        assert_eq!(card.due, Some(now()));
+        assert_eq!(card.fsrs.map(|state| state.stability), Some(3.7145));
+        assert_eq!(
+            card.fsrs.and_then(|state| state.last),
+            Some(time("2021-02-25 12:00:00"))
+        );
+        assert_eq!(card.lapses, 2);
+        assert!(card.state.suspended && card.state.flagged && !card.state.leech);
+        assert_eq!(card.state.buried, None);
+        assert_eq!(card.id.as_deref(), Some("c1cae662"));
+        assert_eq!(card.variantstates.len(), 1);
+        let variant = &card.variantstates[0];
+        assert_eq!(variant.label, "c2");
+        assert_eq!(variant.schedule.deck, 1);
+        assert_eq!(variant.schedule.due, Some(time("2021-03-02 12:00:00")));
+        assert_eq!(variant.lapses, 1);
+        assert!(variant.state.leech);
+        assert_eq!(
+            variant.state.buried,
+            Some(time("2021-03-02 00:00:00").date())
+        );
+        assert_eq!(card.write_to_string(2, false), line);
+
+        //resetting drops the progress, but keeps the state and identifier
+        let card = VocaCard::parse_line(line, None, true, 1).expect("valid line");
+        assert_eq!(
+            card.write_to_string(2, true),
+            "hond\tdog\tstate@suspended,flagged\tid@c1cae662\tc2:state@leech,buried:2021-03-02"
+        );
+        assert!(VocaCard::parse_line("hond\tdog\tstate@asleep", None, false, 1).is_err());
+    }
+
+    #[test]
+    fn expand_directions() {
+        let session = VocaSession::from_arguments(vec![
+            "--columns",
+            "word,translation",
+            "--showcolumns",
+            "word",
+            "--showcolumns",
+            "translation",
+            "--direction",
+            "1,2",
+            "--direction",
+            "2,1",
+        ])
+        .expect("valid session");
+        let parsed = VocaCard::parse_line("hond\tdog\tdeck#1\td2:deck#2", None, false, 1)
+            .expect("valid line");
+        let cards = parsed.expand(&session);
+        assert_eq!(cards.len(), 2);
+        assert_eq!((cards[0].direction, cards[0].variant.as_deref()), (0, None));
+        assert_eq!(
+            (cards[1].direction, cards[1].variant.as_deref()),
+            (1, Some("d2"))
+        );
+        assert_eq!((cards[0].deck, cards[1].deck), (0, 1));
+        let front = |card: &VocaCard| -> Vec<String> {
+            card.fields_to_str(0, &session, false)
+                .expect("valid side")
+                .into_iter()
+                .map(|(_, field)| field.to_string())
+                .collect()
+        };
+        assert_eq!(front(&cards[0]), vec!["hond"]);
+        assert_eq!(front(&cards[1]), vec!["dog"]);
+
+        //every --direction takes exactly one value, so it does not swallow the files that follow it
+        let args = App::new("test")
+            .args(&VocaSession::common_arguments())
+            .arg(Arg::with_name("files").multiple(true))
+            .get_matches_from(vec!["test", "--direction", "2,1", "words.tsv"]);
+        assert_eq!(args.values_of("direction").map(|v| v.count()), Some(1));
+        assert_eq!(args.value_of("files"), Some("words.tsv"));
+
+        //directions from the metadata must still refer to existing sides after --showcolumns on the command line
+        let mut threesided = VocaSession::from_arguments(vec![
+            "--columns",
+            "word,translation,example",
+            "--showcolumns",
+            "word",
+            "--showcolumns",
+            "translation",
+            "--showcolumns",
+            "example",
+            "--direction",
+            "1,3",
+        ])
+        .expect("valid session");
+        let args = App::new("test")
+            .args(&VocaSession::common_arguments())
+            .get_matches_from(vec![
+                "test",
+                "--showcolumns",
+                "word",
+                "--showcolumns",
+                "translation",
+            ]);
+        assert!(matches!(
+            threesided.set_common_arguments(&args),
+            Err(VocaError::InvalidArgument(_))
+        ));
+
+        //siblings are the other directions of the same line
+        let mut cards = cards;
+        cards.extend(card("kat", "cat").expand(&session));
+        let data = dataset(cards, session);
+        assert_eq!(data.siblings(1), vec![0]);
+        assert_eq!(data.siblings(2), vec![3]);
+
+        //siblings of a card that was just presented are excluded from picking
+        let exclude: HashSet<usize> = data.siblings(0).into_iter().chain(Some(0)).collect();
+        let options = PickOptions {
+            exclude: Some(&exclude),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..10 {
+            let (index, count) = data
+                .random_index(&mut rng, &now(), &options)
+                .expect("presentable card");
+            assert!(index >= 2);
+            assert_eq!(count, 2);
+        }
+        assert_eq!(data.next_index(0, &now(), true, &options), Some((2, 1)));
+    }
+
+    #[test]
+    fn update_fields() {
+        let session = VocaSession::from_arguments(vec![
+            "--columns",
+            "word,translation",
+            "--direction",
+            "1,2",
+            "--direction",
+            "2,1",
+        ])
+        .expect("valid session");
+        let mut cards = card("hond", "dog").expand(&session);
+        cards.extend(card("kat", "cat").expand(&session));
+        let mut data = dataset(cards, session);
+        data.update_fields(1, vec!["hond".to_owned(), "hound".to_owned()]);
+        //the siblings share the same line, and therefore the same fields
+        assert_eq!(data.cards[0].fields, vec!["hond", "hound"]);
+        assert_eq!(data.cards[1].fields, vec!["hond", "hound"]);
+        assert_eq!(data.cards[2].fields, vec!["kat", "cat"]);
+
+        //a line edited as text, including the state of the other variants
+        data.cards[1].deck = 1;
+        let line = data.line_to_string(1, false);
+        assert!(line.contains("\td2:deck#2"));
+        let edited = line
+            .replace("\thound\t", "\tdog\t")
+            .replace("\td2:deck#2", "\td2:deck#3");
+        let edited = VocaCard::parse_line(&edited, Some(2), false, 1).expect("valid line");
+        assert_eq!(data.replace_line(1, edited), 1);
+        assert_eq!(data.cards[0].fields, vec!["hond", "dog"]);
+        assert_eq!((data.cards[0].deck, data.cards[1].deck), (0, 2));
+        assert_eq!(data.cards[2].fields, vec!["kat", "cat"]);
+    }
+
+    #[test]
+    fn update_fields_reexpands_clozes() {
+        let mut session = session();
+        session.clozecolumn = Some(0);
+        let mut data = dataset(
+            vec![card("De [hond] eet", "the dog eats"), card("[vis]", "fish")],
+            session,
+        );
+        data.comments.push((1, "# fish".to_owned()));
+        data.expand();
+        assert_eq!(data.cards.len(), 2);
+        let index = data.update_fields(
+            0,
+            vec![
+                "De [hond] eet een [bot]".to_owned(),
+                "the dog eats a bone".to_owned(),
+            ],
+        );
+        assert_eq!(index, 0);
+        assert_eq!(data.cards.len(), 3);
+        assert_eq!(data.cards[1].variant.as_deref(), Some("c2"));
+        assert_eq!(data.comments[0].0, 2);
+        assert_eq!(data.line(1), 0..2);
+    }
+
+    #[test]
+    fn parse_filter() {
+        let session = session();
+        let filter = CardFilter::parse("deck:>=2 due:<7d word=Hond -unseen", &session)
+            .expect("valid filter");
+        assert_eq!(
+            filter.terms,
+            vec![
+                (false, FilterTerm::Deck(Comparison::GreaterOrEqual, 1)),
+                (false, FilterTerm::Due(Comparison::Less, 10080)),
+                (false, FilterTerm::FieldEquals(0, "hond".to_owned())),
+                (true, FilterTerm::Unseen),
+            ]
+        );
+        assert_eq!(
+            CardFilter::parse("deck:weekly", &session)
+                .expect("valid filter")
+                .terms,
+            vec![(false, FilterTerm::Deck(Comparison::Equal, 2))]
+        );
+        assert!(CardFilter::parse("deck:yearly", &session).is_err());
+        assert!(CardFilter::parse("colour=red", &session).is_err());
+        assert!(CardFilter::parse("due:<soon", &session).is_err());
+        //durations that overflow are invalid
+        assert!(CardFilter::parse("due:<9999999999999999w", &session).is_err());
+        assert!(CardFilter::parse("due:<9999999999999999", &session).is_err());
+        assert!(CardFilter::parse("bogus", &session).is_err());
+
+        let filter = CardFilter::parse("translation~do -unseen", &session).expect("valid filter");
+        let mut card = card("hond", "dog");
+        assert!(!filter.matches(&card, &session, &now()));
+        card.due = Some(now());
+        assert!(filter.matches(&card, &session, &now()));
+        card.fields[1] = "cat".to_owned();
+        assert!(!filter.matches(&card, &session, &now()));
+    }
+
+    #[test]
+    fn presentable() {
+        let now = now();
+        let mut card = card("hond", "dog");
+        assert!(card.is_presentable(&now, None, true, false));
+        assert!(!card.is_presentable(&now, None, true, true));
+        card.deck = 1;
+        card.due = Some(time("2021-03-02 12:00:00"));
+        assert!(!card.is_presentable(&now, None, true, false));
+        assert!(card.is_presentable(&now, None, false, true));
+        assert!(card.is_presentable(&now, Some(&vec![1]), false, false));
+        assert!(!card.is_presentable(&now, Some(&vec![0, 2]), false, false));
+        //suspended and buried cards are never presented
+        card.state.suspended = true;
+        assert!(!card.is_presentable(&now, None, false, false));
+        card.state.suspended = false;
+        //buried until local midnight
+        let today = local_date(&now);
+        card.state.buried = Some(today + Duration::days(1));
+        assert!(!card.is_presentable(&local_time(today, 23), None, false, false));
+        assert!(card.is_presentable(
+            &local_time(today + Duration::days(1), 0),
+            None,
+            false,
+            false
+        ));
+        //flagged cards are presented as usual
+        card.state.buried = None;
+        card.state.flagged = true;
+        assert!(card.is_presentable(&now, None, false, false));
+    }
+
+    #[test]
+    fn lapses_and_leeches() {
+        let mut session = session();
+        session.leechthreshold = Some(2);
+        session.leechsuspend = true;
+        let scheduler = session.scheduler();
+        let mut hond = card("hond", "dog");
+        //answering a card on the first deck wrong is not a lapse
+        hond.review(Grade::Again, scheduler.as_ref(), &now(), &session);
+        assert_eq!(hond.lapses, 0);
+        hond.review(Grade::Good, scheduler.as_ref(), &now(), &session);
+        hond.review(Grade::Again, scheduler.as_ref(), &now(), &session);
+        assert_eq!(hond.lapses, 1);
+        assert!(!hond.state.leech);
+        hond.review(Grade::Good, scheduler.as_ref(), &now(), &session);
+        hond.review(Grade::Again, scheduler.as_ref(), &now(), &session);
+        assert_eq!(hond.lapses, 2);
+        assert!(hond.state.leech && hond.state.suspended);
+
+        //moving a card to a lower deck is a lapse as well, moving it up is not
+        let mut cat = card("kat", "cat");
+        assert!(cat.move_to_deck(3, &now(), &session));
+        assert_eq!(cat.lapses, 0);
+        assert!(cat.move_to_deck(1, &now(), &session));
+        assert_eq!(cat.lapses, 1);
+        assert!(!cat.move_to_deck(4, &now(), &session));
+        assert_eq!(cat.deck, 1);
+    }
+
+    #[test]
+    fn daily_limits() {
+        let now = now();
+        let mut session = session();
+        session.newperday = Some(1);
+        session.reviewsperday = Some(2);
+        let new = card("hond", "dog");
+        let mut seen = card("kat", "cat");
+        seen.due = Some(time("2021-02-28 12:00:00"));
+        assert!(session.within_daily_limits(&new, &now));
+        session.count_studied(true, &now);
+        assert!(!session.within_daily_limits(&new, &now));
+        assert!(session.within_daily_limits(&seen, &now));
+        assert!(session.daily_limit_reached(&now));
+        session.count_studied(false, &now);
+        session.count_studied(false, &now);
+        assert!(!session.within_daily_limits(&seen, &now));
+        assert_eq!(
+            session.studied,
+            DailyCounts {
+                date: Some(local_date(&now)),
+                new: 1,
+                reviews: 2,
+            }
+        );
+        //the counts start over on the next (local) day
+        let tomorrow = local_time(local_date(&now) + Duration::days(1), 0);
+        assert!(session.within_daily_limits(&new, &tomorrow));
+        assert!(!session.daily_limit_reached(&tomorrow));
+        assert_eq!(session.studied_on(&tomorrow).new, 0);
+    }
+
+    #[test]
+    fn pick_within_daily_limits() {
+        let now = now();
+        let mut seen = card("kat", "cat");
+        seen.deck = 1;
+        seen.due = Some(time("2021-02-28 12:00:00"));
+        let mut session = session();
+        session.newperday = Some(1);
+        session.count_studied(true, &now);
+        let data = dataset(
+            vec![card("hond", "dog"), seen, card("vis", "fish")],
+            session,
+        );
+        let options = PickOptions::default();
+        let mut rng = StdRng::seed_from_u64(1);
+        //the limit on new cards was reached today, only the review remains
+        assert_eq!(data.random_index(&mut rng, &now, &options), Some((1, 1)));
+        assert_eq!(data.next_index(0, &now, true, &options), Some((1, 0)));
+        //the limits start over on the next day
+        let tomorrow = now + Duration::days(1);
+        assert_eq!(data.next_index(0, &tomorrow, true, &options), Some((0, 2)));
+        assert_eq!(data.next_index(0, &tomorrow, false, &options), Some((1, 1)));
+        let exclude: HashSet<usize> = [0, 1].iter().copied().collect();
+        let options = PickOptions {
+            exclude: Some(&exclude),
+            ..Default::default()
+        };
+        assert_eq!(
+            data.random_index(&mut rng, &tomorrow, &options),
+            Some((2, 1))
+        );
+        let options = PickOptions {
+            seen_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            data.random_index(&mut rng, &tomorrow, &options),
+            Some((1, 1))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let configure = |args: Vec<&str>| VocaSession::from_arguments(args).err();
+        assert!(matches!(
+            configure(vec!["--columns", "word,translation", "--showcolumns", "colour"]),
+            Some(VocaError::UnknownColumn(column)) if column == "colour"
+        ));
+        assert!(matches!(
+            configure(vec!["--decks", "new,daily", "--intervals", "0"]),
+            Some(VocaError::DeckIntervalMismatch {
+                decks: 2,
+                intervals: 1
+            })
+        ));
+        assert!(matches!(
+            configure(vec!["--new-per-day", "many"]),
+            Some(VocaError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            VocaCard::parse_line("hond\tdog\tdue@tomorrow", None, false, 3),
+            Err(VocaError::Parse { line: 3, .. })
+        ));
+        //decks are numbered from 1
+        assert!(VocaCard::parse_line("hond\tdog\tdeck#0", None, false, 1).is_err());
+        assert!(VocaCard::parse_line("hond\tdog\tdeck#x", None, false, 1).is_err());
+        //a dataset that was not loaded from file can not be written
+        assert!(dataset(vec![card("hond", "dog")], session())
+            .write(false)
+            .is_err());
+
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!("vocage-test-{}-{}", std::process::id(), name))
+                .to_str()
+                .expect("valid path")
+                .to_owned()
+        };
+        let (first, second, invalid, nodeck) = (
+            path("first.tsv"),
+            path("second.tsv"),
+            path("invalid.tsv"),
+            path("nodeck.tsv"),
+        );
+        std::fs::write(&first, "#--columns word,translation\nhond\tdog\n").expect("write");
+        std::fs::write(&second, "#--columns word,meaning\nkat\tcat\n").expect("write");
+        std::fs::write(&invalid, "hond\tdog\nkat\tcat\tdue@tomorrow\n").expect("write");
+        std::fs::write(
+            &nodeck,
+            "#--decks new,daily\n#--intervals 0,1440\nhond\tdog\tdeck#3\n",
+        )
+        .expect("write");
+        let missing = path("missing.tsv");
+        let io = load_files(vec![missing.as_str()], false, false, None);
+        let incompatible = load_files(vec![first.as_str(), second.as_str()], false, false, None);
+        let parse = load_files(vec![invalid.as_str()], false, false, None);
+        let deck = load_files(vec![nodeck.as_str()], false, false, None);
+        for file in [&first, &second, &invalid, &nodeck].iter() {
+            std::fs::remove_file(file).expect("remove");
+        }
+        assert!(matches!(io, Err(VocaError::Io(_))));
+        assert!(matches!(
+            incompatible,
+            Err(VocaError::IncompatibleDatasets { filename, .. }) if filename == second
+        ));
+        //an invalid special column is reported for its line, the rest of the file is loaded
+        let parse = parse.expect("loaded despite invalid line");
+        assert_eq!(parse[0].cards.len(), 2);
+        assert_eq!(parse[0].warnings.len(), 1);
+        assert!(matches!(
+            parse[0].warnings[0],
+            VocaError::Parse { line: 2, .. }
+        ));
+        assert!(parse[0].warnings[0]
+            .to_string()
+            .starts_with(&format!("{}, line 2:", invalid)));
+        //the invalid value is retained so it is written back as it was
+        assert!(parse[0].cards[1]
+            .write_to_string(2, false)
+            .starts_with("kat\tcat\tdue@tomorrow\t"));
+        assert!(matches!(deck, Err(VocaError::Parse { line: 3, .. })));
+    }
+
+    #[test]
+    fn seeded_order() {
+        let cards = [
+            "hond", "kat", "vis", "paard", "koe", "schaap", "geit", "kip",
+        ]
+        .iter()
+        .map(|word| card(word, ""))
+        .collect();
+        let datasets = vec![dataset(cards, session())];
+        let order = |seed: u64| -> Vec<usize> {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut order = Vec::new();
+            let mut exclude: HashSet<usize> = HashSet::new();
+            while let Some((index, _)) = datasets[0].random_index(
+                &mut rng,
+                &now(),
+                &PickOptions {
+                    exclude: Some(&exclude),
+                    ..Default::default()
+                },
+            ) {
+                exclude.insert(index);
+                order.push(index);
+            }
+            order.extend(
+                pick_distractors(&datasets, 0, 0, 0, 3, &mut rng)
+                    .iter()
+                    .filter_map(|word| {
+                        datasets[0]
+                            .cards
+                            .iter()
+                            .position(|card| &card.fields[0] == word)
+                    }),
+            );
+            order
+        };
+        //the same seed yields the same order of cards and distractors
+        assert_eq!(order(42), order(42));
+        assert_eq!(order(42).len(), 11);
+    }
+
+    #[test]
+    fn load_with_cloze_argument() {
+        let path =
+            std::env::temp_dir().join(format!("vocage-test-{}-cloze.tsv", std::process::id()));
+        let filename = path.to_str().expect("valid path");
+        std::fs::write(
+            filename,
+            "#--columns sentence,translation\nDe [hond] eet\tthe dog eats\n#animals\n[kat] en [muis]\tcat and mouse\tc2:deck#2\n",
+        )
+        .expect("write");
+        let args = App::new("test")
+            .args(&VocaSession::common_arguments())
+            .get_matches_from(vec!["test", "--cloze", "sentence"]);
+        let plain = load_files(vec![filename], false, false, None);
+        let clozes = load_files(vec![filename], false, false, Some(&args));
+        std::fs::remove_file(filename).expect("remove");
+
+        let plain = plain.expect("valid file").remove(0);
+        assert_eq!(plain.cards.len(), 2);
+        //the command line arguments are applied before the lines are split into clozes
+        let mut clozes = clozes.expect("valid file").remove(0);
+        assert_eq!(clozes.cards.len(), 3);
+        assert_eq!(clozes.cards[2].variant.as_deref(), Some("c2"));
+        assert_eq!(clozes.cards[2].deck, 1);
+        assert_eq!(clozes.comments[0].0, 1);
+        assert_eq!(clozes.session.showcolumns, vec![vec![0], vec![0, 1]]);
+        //expanding again yields the same cards
+        clozes.expand();
+        assert_eq!(clozes.cards.len(), 3);
+        assert_eq!(clozes.comments[0].0, 1);
+    }
+
+    #[test]
+    fn tags() {
+        let tagged = VocaSession::from_arguments(vec!["--columns", "word,tags,translation"])
+            .expect("valid session");
+        assert_eq!(tagged.tagcolumn(), Some(1));
+        //the tags column is not shown by default, also not for cloze deletions
+        assert_eq!(tagged.showcolumns, vec![vec![0], vec![2]]);
+        let clozes = VocaSession::from_arguments(vec![
+            "--columns",
+            "word,tags,translation",
+            "--cloze",
+            "word",
+        ])
+        .expect("valid session");
+        assert_eq!(clozes.showcolumns, vec![vec![0], vec![0, 2]]);
+
+        let mut card =
+            VocaCard::parse_line("hond\tanimal\tdog", Some(3), false, 1).expect("valid line");
+        assert!(card.has_tag("animal", &tagged));
+        assert_eq!(card.toggle_tag("pet", &tagged), Some(true));
+        assert_eq!(card.fields[1], "animal pet");
+        assert_eq!(card.toggle_tag("animal", &tagged), Some(false));
+        assert_eq!(card.fields[1], "pet");
+        assert_eq!(card.toggle_tag("pet", &session()), None);
+    }
+
+    #[test]
+    fn stats_skip_undone_actions() {
+        let later = |card: &str, action: ReviewAction, minutes: i64| -> ReviewLogEntry {
+            ReviewLogEntry {
+                timestamp: now() + Duration::minutes(minutes),
+                ..entry(card, action)
+            }
+        };
+        let mut stats = VocaStats::default();
+        stats.add_log(&[
+            entry("hond", ReviewAction::Graded(Grade::Good)),
+            entry("kat", ReviewAction::Graded(Grade::Good)),
+            later("hond", ReviewAction::Graded(Grade::Again), 1),
+            later("hond", ReviewAction::Skipped, 2),
+            later(
+                "hond",
+                ReviewAction::Undone(Some(now() + Duration::minutes(1))),
+                3,
+            ),
+            later("hond", ReviewAction::Graded(Grade::Good), 4),
+        ]);
+        //the wrong answer was undone (not the skip after it) and the card answered again
+        assert_eq!(stats.reviews, 3);
+        assert_eq!((stats.recalled, stats.recallable), (1, 1));
+
+        //without a reference the last grade or move is undone, skips are passed over
+        let mut stats = VocaStats::default();
+        stats.add_log(&[
+            entry("kat", ReviewAction::Graded(Grade::Good)),
+            entry("kat", ReviewAction::Graded(Grade::Again)),
+            entry("kat", ReviewAction::Skipped),
+            entry("kat", ReviewAction::Undone(None)),
+        ]);
+        assert_eq!(stats.reviews, 1);
+
+        assert_eq!(
+            "undo".parse::<ReviewAction>(),
+            Ok(ReviewAction::Undone(None))
+        );
+        let undone = ReviewAction::Undone(Some(now()));
+        assert_eq!(undone.to_string(), "undo@2021-03-01 12:00:00");
+        assert_eq!(undone.to_string().parse::<ReviewAction>(), Ok(undone));
+    }
 }